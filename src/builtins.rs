@@ -1,26 +1,156 @@
-use crate::crisp::{Environment, EvalError, EvalResult, Function, Integer, Symbol, Value};
+use crate::crisp::{
+    Defun, Environment, EvalError, EvalResult, Float, Function, HashKey, Integer, Quote, Symbol,
+    Value, OPTIONAL,
+};
+
+use crate::parsers::parse;
+
+#[cfg(feature = "bignum")]
+use crate::crisp::BigInt;
+#[cfg(feature = "bignum")]
+use num_integer::Integer as _;
+#[cfg(feature = "bignum")]
+use num_traits::{FromPrimitive, Signed, ToPrimitive};
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type Builtin = fn(&mut Environment, Vec<Value>) -> EvalResult;
 
 pub fn configure(environment: &mut Environment) {
-    let functions: Vec<(&str, fn(&mut Environment, Vec<Value>) -> EvalResult)> = vec![
+    let functions: Vec<(&str, Builtin)> = vec![
         ("progn", progn),
+        ("values", values),
+        ("multiple-value-bind", multiple_value_bind),
+        ("quote", quote),
+        ("quasiquote", quasiquote),
+        ("eval", eval),
         ("debug", debug),
+        ("print", print),
+        ("pprint", pprint),
+        ("println", println),
+        ("read-line", read_line),
         ("if", if_),
+        ("when", when),
+        ("unless", unless),
         ("while", while_),
+        ("catch", catch),
+        ("throw", throw),
+        ("error", error),
+        ("condition-case", condition_case),
+        ("unwind-protect", unwind_protect),
+        ("and", and),
+        ("or", or),
+        ("not", not),
+        ("integerp", integerp),
+        ("stringp", stringp),
+        ("symbolp", symbolp),
+        ("boundp", boundp),
+        ("fboundp", fboundp),
+        ("listp", listp),
+        ("null", null),
+        ("booleanp", booleanp),
         ("set", set),
+        ("setf", setf),
         ("let", let_),
+        ("let*", let_star),
+        ("let-dynamic", let_dynamic),
+        ("block", block),
+        ("defvar", defvar),
+        ("defconst", defconst),
+        ("makunbound", makunbound),
+        ("fmakunbound", fmakunbound),
+        ("variable-documentation", variable_documentation),
+        ("incf", incf),
+        ("decf", decf),
         ("=", eq),
         ("/=", neq),
+        ("<", lt),
+        (">", gt),
+        ("<=", le),
+        (">=", ge),
         ("+", add),
         ("-", sub),
         ("*", mul),
         ("/", div),
+        ("mod", mod_),
+        ("%", mod_),
+        ("floor", floor),
+        ("ceiling", ceiling),
+        ("round", round),
+        ("truncate", truncate),
+        ("min", min),
+        ("max", max),
+        ("abs", abs),
+        ("expt", expt),
+        ("gcd", gcd),
+        ("lcm", lcm),
+        ("logand", logand),
+        ("logior", logior),
+        ("logxor", logxor),
+        ("lognot", lognot),
+        ("ash", ash),
         ("car", car),
         ("cdr", cdr),
+        ("cons", cons),
+        ("list", list),
+        ("length", length),
+        ("reverse", reverse),
+        ("append", append),
+        ("last", last),
+        ("member", member),
+        ("position", position),
+        ("find", find),
+        ("assoc", assoc),
+        ("concat", concat),
+        ("format", format),
+        ("substring", substring),
+        ("upcase", upcase),
+        ("downcase", downcase),
+        ("split", split),
+        ("char-at", char_at),
+        ("char-code", char_code),
+        ("code-char", code_char),
+        ("make-string", make_string),
+        ("string", string),
+        ("to-string", to_string),
+        ("read", read),
+        ("load", load),
+        ("require", require),
+        ("read-file", read_file),
+        ("write-file", write_file),
+        ("getenv", getenv),
+        ("command-line-args", command_line_args),
+        ("now", now),
+        ("make-hash", make_hash),
+        ("gethash", gethash),
+        ("puthash", puthash),
+        ("nth", nth),
+        ("elt", elt),
+        ("gensym", gensym),
+        ("symbol-name", symbol_name),
+        ("intern", intern),
+        ("make-symbol", intern),
         ("defun", defun),
+        ("in-package", in_package),
+        ("defmacro", defmacro),
+        ("macroexpand", macroexpand),
+        ("lambda", lambda),
+        ("mapcar", mapcar),
+        ("reduce", reduce),
+        ("filter", filter),
+        ("sort", sort),
+        ("apply", apply),
+        ("funcall", funcall),
+        ("function", function),
+        ("trace", trace),
+        ("untrace", untrace),
     ];
 
     for (name, function) in functions {
-        environment.add_function(Symbol::from_str(name), Function::new_builtin(function));
+        environment.register(name, function);
     }
 }
 
@@ -32,37 +162,75 @@ fn mismatch<R>(environment: &mut Environment, reason: &str) -> Result<R, EvalErr
     )))
 }
 
+fn single_arg(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() == 1 {
+        args[0].eval(environment)
+    } else {
+        mismatch(environment, "This function takes exactly one argument")
+    }
+}
+
 fn list_arg(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
     if args.len() == 1 {
         let value = args[0].eval(environment)?;
 
         match value {
-            Value::List(_) => Ok(value),
-            _ => mismatch(environment, "This function takes a list".into()),
+            Value::List(_) | Value::Dotted(_, _) => Ok(value),
+            _ => mismatch(environment, "This function takes a list"),
         }
     } else {
-        mismatch(
-            environment,
-            "This function takes exactly one list argument".into(),
-        )
+        mismatch(environment, "This function takes exactly one list argument")
+    }
+}
+
+/// The elements of an evaluated list argument, with `nil` standing for the empty list.
+fn elements(environment: &mut Environment, value: Value) -> Result<Vec<Value>, EvalError> {
+    match value {
+        Value::Nil => Ok(Vec::new()),
+        Value::List(elements) => Ok(elements),
+        Value::Dotted(..) => mismatch(environment, "Expected a proper list, not an improper list"),
+        _ => mismatch(environment, "Expected a list"),
+    }
+}
+
+fn arithmetic_overflow<R>(environment: &mut Environment) -> Result<R, EvalError> {
+    Err(EvalError::ArithmeticOverflow(
+        environment.current().caller.to_string(),
+    ))
+}
+
+fn checked<T>(environment: &mut Environment, result: Option<T>) -> Result<T, EvalError> {
+    match result {
+        Some(value) => Ok(value),
+        None => arithmetic_overflow(environment),
     }
 }
 
-fn reduce<T, C: Fn(Value) -> Option<T>, R: Fn(T, T) -> T>(
+fn division_by_zero<R>(environment: &mut Environment) -> Result<R, EvalError> {
+    Err(EvalError::DivisionByZero(
+        environment.current().caller.to_string(),
+    ))
+}
+
+fn try_reduce<T, C, R>(
     environment: &mut Environment,
     starting: Value,
     args: Vec<Value>,
     conversion: C,
     operation: R,
-) -> Result<T, EvalError> {
+) -> Result<T, EvalError>
+where
+    C: Fn(Value) -> Option<T>,
+    R: Fn(&mut Environment, T, T) -> Result<T, EvalError>,
+{
     let mut starting = match conversion(starting.eval(environment)?) {
         Some(some) => some,
-        None => return mismatch(environment, "Couldn't convert the starting value".into()),
+        None => return mismatch(environment, "Couldn't convert the starting value"),
     };
 
     for value in args.iter() {
         match conversion(value.eval(environment)?) {
-            Some(converted) => starting = operation(starting, converted),
+            Some(converted) => starting = operation(environment, starting, converted)?,
             None => {
                 return mismatch(
                     environment,
@@ -75,35 +243,78 @@ fn reduce<T, C: Fn(Value) -> Option<T>, R: Fn(T, T) -> T>(
     Ok(starting)
 }
 
-fn reduce_car_cdr<T, C: Fn(Value) -> Option<T>, R: Fn(T, T) -> T>(
+fn try_reduce_car_cdr<T, C, R>(
     environment: &mut Environment,
     args: Vec<Value>,
     conversion: C,
     operation: R,
-) -> Result<T, EvalError> {
+) -> Result<T, EvalError>
+where
+    C: Fn(Value) -> Option<T>,
+    R: Fn(&mut Environment, T, T) -> Result<T, EvalError>,
+{
     let car = match args.first() {
         Some(value) => value,
-        None => return mismatch(environment, "No car in the list".into()),
+        None => return mismatch(environment, "No car in the list"),
     };
 
     let cdr = args[1..].to_vec();
 
-    reduce(environment, car.clone(), cdr, conversion, operation)
+    try_reduce(environment, car.clone(), cdr, conversion, operation)
+}
+
+fn reduce_car_cdr<T, C: Fn(Value) -> Option<T>, R: Fn(T, T) -> T>(
+    environment: &mut Environment,
+    args: Vec<Value>,
+    conversion: C,
+    operation: R,
+) -> Result<T, EvalError> {
+    try_reduce_car_cdr(environment, args, conversion, |_, x, y| Ok(operation(x, y)))
+}
+
+/// Wrap a value in `quote` unless it already evaluates to itself.
+fn quoted(value: Value) -> Value {
+    match value {
+        Value::Funcall(_, _) | Value::List(_) | Value::Dotted(_, _) => (),
+        Value::Symbol(ref symbol) if symbol.quote != Quote::Single => (),
+        value => return value,
+    }
+
+    Value::Funcall(
+        Box::new(Value::Symbol(Symbol::from_str("quote"))),
+        vec![value],
+    )
+}
+
+/// Call a function value with already evaluated args.
+fn invoke(environment: &mut Environment, function: &Value, args: Vec<Value>) -> EvalResult {
+    let args = args.into_iter().map(quoted).collect();
+
+    let result = match function {
+        Value::Symbol(symbol) => environment.call(&symbol.unquoted(), args),
+        Value::Lambda(lambda) => environment.call_lambda(lambda, args),
+        _ => mismatch(
+            environment,
+            format!("Not a function: {:?}", function).as_str(),
+        ),
+    };
+
+    result.map(Value::first_value)
 }
 
 fn make_progn(args: Vec<Value>) -> Value {
-    Value::Funcall(Symbol::from_str("progn"), args)
+    Value::Funcall(Box::new(Value::Symbol(Symbol::from_str("progn"))), args)
 }
 
 fn some_args(environment: &mut Environment, args: Vec<Value>) -> Result<Vec<Value>, EvalError> {
-    if args.len() == 0 {
-        mismatch(environment, "This function takes one or more args".into())
+    if args.is_empty() {
+        mismatch(environment, "This function takes one or more args")
     } else {
         Ok(args)
     }
 }
 
-fn is_nil(value: &Value) -> bool {
+pub fn is_nil(value: &Value) -> bool {
     match value {
         Value::Nil => true,
         Value::List(elements) => elements.is_empty(),
@@ -121,7 +332,137 @@ fn progn(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
         arg.eval(environment)?;
     }
 
-    args.last().unwrap_or(&Value::Nil).eval(environment)
+    args.last().unwrap_or(&Value::Nil).eval_values(environment)
+}
+
+fn values(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let mut values: Vec<Value> = Vec::new();
+
+    for arg in args.iter() {
+        values.push(arg.eval(environment)?);
+    }
+
+    Ok(Value::Values(values))
+}
+
+/// Bind each symbol to one of the values of a form, or nil when there are fewer of them.
+fn multiple_value_bind(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let symbols = match args.as_slice() {
+        [Value::List(symbols), _, ..] => symbols.clone(),
+        [Value::Nil, _, ..] => Vec::new(),
+        _ => {
+            return mismatch(
+                environment,
+                "This function takes a list of symbols, a form and a body",
+            )
+        }
+    };
+
+    let mut names: Vec<Symbol> = Vec::new();
+
+    for symbol in symbols {
+        match symbol {
+            Value::Symbol(symbol) => names.push(symbol.unquoted()),
+            _ => return mismatch(environment, "Binding names must be symbols"),
+        }
+    }
+
+    let mut values = match args[1].eval_values(environment)? {
+        Value::Values(values) => values.into_iter(),
+        value => vec![value].into_iter(),
+    };
+
+    environment.push_to_stack(&"multiple-value-bind".into());
+
+    for symbol in names {
+        let value = values.next().unwrap_or(Value::Nil);
+        environment.current().put(symbol, value);
+    }
+
+    let result = progn(environment, args[2..].to_vec());
+
+    environment.pop();
+
+    result
+}
+
+fn quote(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    match args.as_slice() {
+        [value] => Ok(value.clone()),
+        _ => mismatch(environment, "This function takes exactly one argument"),
+    }
+}
+
+fn quasiquote(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    match args.as_slice() {
+        [template] => fill_template(environment, template),
+        _ => mismatch(environment, "This function takes exactly one argument"),
+    }
+}
+
+/// Substitute `,symbol` and `,(...)` in a quasiquote template with their values.
+fn fill_template(environment: &mut Environment, template: &Value) -> EvalResult {
+    match template {
+        Value::Symbol(symbol) if symbol.quote == Quote::Eval => {
+            Value::Symbol(symbol.unquoted()).eval(environment)
+        }
+        Value::Funcall(head, args) if **head == Value::Symbol(Symbol::from_str("unquote")) => {
+            match args.as_slice() {
+                [form] => form.eval(environment),
+                _ => mismatch(environment, "Unquote takes exactly one form"),
+            }
+        }
+        Value::Funcall(head, args) => {
+            let head = fill_template(environment, head)?;
+            let args = fill_templates(environment, args)?;
+
+            Ok(Value::Funcall(Box::new(head), args))
+        }
+        Value::List(elements) => Ok(Value::List(fill_templates(environment, elements)?)),
+        _ => Ok(template.clone()),
+    }
+}
+
+fn fill_templates(
+    environment: &mut Environment,
+    templates: &[Value],
+) -> Result<Vec<Value>, EvalError> {
+    let mut filled: Vec<Value> = Vec::new();
+
+    for template in templates {
+        filled.push(fill_template(environment, template)?);
+    }
+
+    Ok(filled)
+}
+
+fn eval(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    match single_arg(environment, args)? {
+        // A list starting with a function name is treated as a call, so code can be built with
+        // `list` and `cons`.
+        Value::List(elements) => {
+            if let Some((Value::Symbol(symbol), args)) = elements.split_first() {
+                let head = Value::Symbol(symbol.unquoted());
+                return Value::Funcall(Box::new(head), args.to_vec()).eval(environment);
+            }
+
+            Value::List(elements).eval(environment)
+        }
+        value => value.eval(environment),
+    }
+}
+
+/// Write to the environment's output, flushing so that prompts without a newline show up.
+fn write_output(environment: &mut Environment, text: &str) -> Result<(), EvalError> {
+    let output = environment.output();
+
+    match output
+        .write_all(text.as_bytes())
+        .and_then(|_| output.flush())
+    {
+        Ok(()) => Ok(()),
+        Err(err) => Err(EvalError::FailedToWriteOutput(err)),
+    }
 }
 
 fn debug(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
@@ -133,21 +474,66 @@ fn debug(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
 
     for arg in args {
         last = arg.eval(environment)?;
-        println!("{:?}", last);
+        write_output(environment, &format!("{:?}\n", last))?;
     }
 
     Ok(last.clone())
 }
 
+/// Render a value for output: strings as their contents, anything else as source.
+fn to_raw_string(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        Value::Char(character) => character.to_string(),
+        value => value.to_string(),
+    }
+}
+
+fn print(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let value = single_arg(environment, args)?;
+
+    write_output(environment, &to_raw_string(&value))?;
+
+    Ok(value)
+}
+
+fn println(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let value = single_arg(environment, args)?;
+
+    write_output(environment, &format!("{}\n", to_raw_string(&value)))?;
+
+    Ok(value)
+}
+
+fn pprint(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let value = single_arg(environment, args)?;
+
+    write_output(environment, &format!("{}\n", value.pretty()))?;
+
+    Ok(value)
+}
+
+fn read_line(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if !args.is_empty() {
+        return mismatch(environment, "This function takes no args");
+    }
+
+    match environment.read_line() {
+        Ok(Some(line)) => Ok(Value::String(line)),
+        Ok(None) => Ok(Value::Nil),
+        Err(err) => Err(EvalError::FailedToReadFile("stdin".into(), err)),
+    }
+}
+
 fn if_(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
-    let condition = match args.get(0) {
+    let condition = match args.first() {
         Some(value) => !is_nil(&value.eval(environment)?),
-        None => return mismatch(environment, "This function takes a condition".into()),
+        None => return mismatch(environment, "This function takes a condition"),
     };
 
     let if_true = match args.get(1) {
         Some(value) => value,
-        None => return mismatch(environment, "This function takes a 'when' parameter".into()),
+        None => return mismatch(environment, "This function takes a 'when' parameter"),
     };
 
     if condition {
@@ -157,26 +543,215 @@ fn if_(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
     }
 }
 
+fn conditional(environment: &mut Environment, args: Vec<Value>, expected: bool) -> EvalResult {
+    let condition = match args.first() {
+        Some(value) => !is_nil(&value.eval(environment)?),
+        None => return mismatch(environment, "This function takes a condition"),
+    };
+
+    if condition == expected {
+        make_progn(args[1..].to_vec()).eval(environment)
+    } else {
+        Ok(Value::Nil)
+    }
+}
+
+fn when(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    conditional(environment, args, true)
+}
+
+fn unless(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    conditional(environment, args, false)
+}
+
 fn while_(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() < 2 {
+        return mismatch(environment, "This function takes a condition and loop body");
+    }
+
+    let condition = args.first().unwrap();
+    let action = make_progn(args[1..].to_vec());
+
+    let mut last = Value::Nil;
+
+    while !is_nil(&condition.eval(environment)?) {
+        last = action.eval(environment)?;
+    }
+
+    Ok(last)
+}
+
+fn catch(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let expected = match args.first() {
+        Some(tag) => tag.eval(environment)?,
+        None => return mismatch(environment, "This function takes a tag and optional body"),
+    };
+
+    match make_progn(args[1..].to_vec()).eval(environment) {
+        Err(EvalError::Thrown { tag, value }) if tag == expected => {
+            environment.clear_backtrace();
+            Ok(value)
+        }
+        result => result,
+    }
+}
+
+fn throw(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() != 2 {
+        return mismatch(environment, "This function takes a tag and a value");
+    }
+
+    Err(EvalError::Thrown {
+        tag: args[0].eval(environment)?,
+        value: args[1].eval(environment)?,
+    })
+}
+
+fn error(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    match single_arg(environment, args)? {
+        Value::String(message) => Err(EvalError::UserError(message)),
+        _ => mismatch(environment, "This function takes an error message"),
+    }
+}
+
+/// `(condition-case var body handler...)`: evaluate `body`, and if it fails, run the handler
+/// with the error message bound to `var`. Throws aren't errors and pass through untouched.
+fn condition_case(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
     if args.len() < 2 {
         return mismatch(
             environment,
-            "This function takes a condition and loop body".into(),
+            "This function takes a variable, body and handler",
         );
     }
 
-    loop {
-        let condition = args.first().unwrap();
-        let action = make_progn(args[1..].to_vec());
+    let variable = match &args[0] {
+        Value::Symbol(symbol) => Some(symbol.unquoted()),
+        Value::Nil => None,
+        _ => return mismatch(environment, "The first argument must be a symbol"),
+    };
+
+    let message = match args[1].eval(environment) {
+        Err(EvalError::UserError(message)) => message,
+        Err(error @ EvalError::Thrown { .. }) => return Err(error),
+        Err(error) => error.to_string(),
+        result => return result,
+    };
+
+    environment.clear_backtrace();
+    environment.push_to_stack(&"condition-case".into());
+
+    if let Some(variable) = variable {
+        environment.current().put(variable, Value::String(message));
+    }
+
+    let result = make_progn(args[2..].to_vec()).eval(environment);
+
+    environment.pop();
+
+    result
+}
+
+fn unwind_protect(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let result = match args.first() {
+        Some(form) => form.eval(environment),
+        None => return mismatch(environment, "This function takes a form and cleanup forms"),
+    };
+
+    // Errors in the cleanup take precedence, as there's no way to report both.
+    progn(environment, args[1..].to_vec())?;
+
+    result
+}
+
+fn and(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let mut last = Value::T;
 
-        if is_nil(&condition.eval(environment)?) {
+    for arg in args.iter() {
+        last = arg.eval(environment)?;
+
+        if is_nil(&last) {
             return Ok(Value::Nil);
         }
+    }
+
+    Ok(last)
+}
+
+fn or(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    for arg in args.iter() {
+        let value = arg.eval(environment)?;
+
+        if !is_nil(&value) {
+            return Ok(value);
+        }
+    }
+
+    Ok(Value::Nil)
+}
+
+fn not(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    predicate(environment, args, is_nil)
+}
+
+fn predicate(
+    environment: &mut Environment,
+    args: Vec<Value>,
+    test: fn(&Value) -> bool,
+) -> EvalResult {
+    Ok(test(&single_arg(environment, args)?).into())
+}
+
+fn integerp(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    predicate(environment, args, |value| match value {
+        Value::Integer(_) => true,
+        #[cfg(feature = "bignum")]
+        Value::BigInt(_) => true,
+        _ => false,
+    })
+}
+
+fn stringp(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    predicate(environment, args, |value| matches!(value, Value::String(_)))
+}
+
+fn symbolp(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    predicate(environment, args, |value| matches!(value, Value::Symbol(_)))
+}
 
-        action.eval(environment)?;
+/// Evaluate the single arg of a function that takes a symbol, unquoting it.
+fn symbol_arg(environment: &mut Environment, args: Vec<Value>) -> Result<Symbol, EvalError> {
+    match single_arg(environment, args)? {
+        Value::Symbol(symbol) => Ok(symbol.unquoted()),
+        _ => mismatch(environment, "This function takes a symbol"),
     }
 }
 
+fn boundp(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let symbol = symbol_arg(environment, args)?;
+    Ok(Value::from(environment.lookup(&symbol).is_some()))
+}
+
+fn fboundp(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let symbol = symbol_arg(environment, args)?;
+    Ok(Value::from(environment.function(&symbol).is_some()))
+}
+
+fn listp(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    predicate(environment, args, |value| {
+        matches!(value, Value::List(_) | Value::Dotted(_, _) | Value::Nil)
+    })
+}
+
+fn null(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    predicate(environment, args, is_nil)
+}
+
+fn booleanp(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    predicate(environment, args, |value| {
+        matches!(value, Value::T | Value::Nil)
+    })
+}
+
 fn symbol_binding(
     environment: &mut Environment,
     symbol: Value,
@@ -184,7 +759,7 @@ fn symbol_binding(
 ) -> Result<(Symbol, Value), EvalError> {
     match symbol.eval(environment)? {
         Value::Symbol(symbol) => Ok((symbol, value.eval(environment)?)),
-        _ => mismatch(environment, "First argument must be a symbol".into()),
+        _ => mismatch(environment, "First argument must be a symbol"),
     }
 }
 
@@ -193,10 +768,7 @@ fn symbol_binding_argslist(
     args: Vec<Value>,
 ) -> Result<(Symbol, Value), EvalError> {
     if args.len() != 2 {
-        return mismatch(
-            environment,
-            "This function takes a symbol and its value".into(),
-        );
+        return mismatch(environment, "This function takes a symbol and its value");
     }
 
     symbol_binding(
@@ -209,112 +781,1730 @@ fn symbol_binding_argslist(
 fn set(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
     let (symbol, value) = symbol_binding_argslist(environment, args)?;
 
-    if let Some(closure) = environment.find_closure(&symbol) {
-        closure.put(symbol, value.clone());
-    } else {
-        environment.top_level().put(symbol, value.clone());
-    }
+    environment.assign(symbol, value.clone())?;
 
     Ok(value)
 }
 
-fn let_(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
-    let (symbol, value) = symbol_binding_argslist(environment, args)?;
-    environment.outer().put(symbol, value.clone());
-    Ok(value)
-}
+/// Handle `(name value [documentation])`, returning the name and the value form.
+fn definition(
+    environment: &mut Environment,
+    args: Vec<Value>,
+) -> Result<(Symbol, Value), EvalError> {
+    let (name, value, documentation) = match args.as_slice() {
+        [Value::Symbol(name), value] => (name, value, None),
+        [Value::Symbol(name), value, Value::String(documentation)] => {
+            (name, value, Some(documentation))
+        }
+        _ => {
+            return mismatch(
+                environment,
+                "This function takes a symbol, its value, and optional documentation",
+            )
+        }
+    };
 
-fn eq(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
-    reduce_car_cdr(
-        environment,
-        args,
-        |x| Some(x),
-        |x, y| match x == y {
-            true => Value::T,
-            false => Value::Nil,
-        },
-    )
-}
+    let name = name.unquoted();
 
-fn neq(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
-    match eq(environment, args)? {
-        Value::T => Ok(Value::Nil),
-        _ => Ok(Value::T),
+    if let Some(documentation) = documentation {
+        environment.set_documentation(&name, documentation.clone());
     }
+
+    Ok((name, value.clone()))
 }
 
-fn to_integer(value: Value) -> Option<Integer> {
-    match value {
-        Value::Integer(i) => Some(i),
-        _ => None,
+fn defvar(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let (name, value) = definition(environment, args)?;
+
+    if !environment.top_level().has(&name) {
+        let value = value.eval(environment)?;
+        environment.define(name, value, false);
     }
+
+    Ok(Value::Nil)
 }
 
-fn add(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
-    let args = some_args(environment, args)?;
+fn defconst(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let (name, value) = definition(environment, args)?;
 
-    Ok(Value::Integer(reduce(
-        environment,
-        Value::Integer(0),
-        args,
-        to_integer,
-        |x, y| x + y,
-    )?))
+    let value = value.eval(environment)?;
+    environment.define(name, value, true);
+
+    Ok(Value::Nil)
 }
 
-fn sub(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
-    if args.len() == 1 {
-        match args.first().unwrap() {
-            Value::Integer(i) => Ok(Value::Integer(-i)),
-            _ => mismatch(
+fn makunbound(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let symbol = symbol_arg(environment, args)?;
+    environment.unbind(&symbol)?;
+    Ok(Value::Symbol(symbol))
+}
+
+fn fmakunbound(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let symbol = symbol_arg(environment, args)?;
+    environment.remove_function(&symbol);
+    Ok(Value::Symbol(symbol))
+}
+
+fn variable_documentation(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    match single_arg(environment, args)? {
+        Value::Symbol(symbol) => Ok(match environment.documentation(&symbol) {
+            Some(documentation) => Value::String(documentation.clone()),
+            None => Value::Nil,
+        }),
+        _ => mismatch(environment, "This function takes a symbol"),
+    }
+}
+
+/// `(setf place value...)`: like `set`, but also updates elements of lists and hash maps bound
+/// to variables, e.g. `(setf (nth 1 list) 99)` or `(setf (gethash key map) value)`.
+fn setf(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return mismatch(
+            environment,
+            "This function takes pairs of places and values",
+        );
+    }
+
+    let mut value = Value::Nil;
+
+    for pair in args.chunks(2) {
+        value = pair[1].eval(environment)?;
+        set_place(environment, &pair[0], value.clone())?;
+    }
+
+    Ok(value)
+}
+
+/// Store `value` in `place`, then store the updated container in the place it came from, and so
+/// on up to the variable holding it.
+fn set_place(environment: &mut Environment, place: &Value, value: Value) -> Result<(), EvalError> {
+    let (accessor, args) = match place {
+        Value::Symbol(symbol) if symbol.quote == Quote::None => {
+            return environment.assign(symbol.clone(), value)
+        }
+        Value::Funcall(head, args) => match head.as_ref() {
+            Value::Symbol(accessor) => (accessor.name.as_str(), args.as_slice()),
+            _ => return mismatch(environment, format!("Not a place: {}", place).as_str()),
+        },
+        _ => return mismatch(environment, format!("Not a place: {}", place).as_str()),
+    };
+
+    let (index, inner) = match (accessor, args) {
+        ("gethash", [key, inner] | [key, inner, _]) => {
+            let key = key.eval(environment)?;
+            let key = hash_key(environment, key)?;
+            let mut map = hash_map_arg(environment, inner)?;
+
+            map.insert(key, value);
+
+            return set_place(environment, inner, Value::HashMap(map));
+        }
+        ("nth", [index, inner]) | ("elt", [inner, index]) => {
+            (index_arg(environment, index)?, inner)
+        }
+        ("car", [inner]) => (0, inner),
+        _ => return mismatch(environment, format!("Not a place: {}", place).as_str()),
+    };
+
+    let list = inner.eval(environment)?;
+    let mut elements = elements(environment, list)?;
+
+    match elements.get_mut(index) {
+        Some(element) => *element = value,
+        None => return mismatch(environment, "Index out of bounds"),
+    }
+
+    set_place(environment, inner, Value::List(elements))
+}
+
+fn let_(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let (symbol, value) = symbol_binding_argslist(environment, args)?;
+
+    match environment.outer() {
+        Some(frame) => frame.put(symbol, value.clone()),
+        None => environment.top_level().put(symbol, value.clone()),
+    }
+
+    Ok(value)
+}
+
+fn let_star(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let bindings = match args.first() {
+        Some(Value::List(bindings)) => bindings.clone(),
+        Some(Value::Nil) => Vec::new(),
+        _ => {
+            return mismatch(
+                environment,
+                "This function takes a list of bindings and a body",
+            )
+        }
+    };
+
+    environment.push_to_stack(&"let*".into());
+
+    let result = bind_sequentially(environment, bindings)
+        .and_then(|_| make_progn(args[1..].to_vec()).eval(environment));
+
+    environment.pop();
+
+    result
+}
+
+/// Rebind top-level variables for the duration of the body, so that functions called from it
+/// see the new values too. The old values are restored however the body exits.
+fn let_dynamic(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let bindings = match args.first() {
+        Some(Value::List(bindings)) => bindings.clone(),
+        Some(Value::Nil) => Vec::new(),
+        _ => {
+            return mismatch(
                 environment,
-                "This function takes one or more integer values".into(),
-            ),
+                "This function takes a list of bindings and a body",
+            )
+        }
+    };
+
+    let mut values: Vec<(Symbol, Value)> = Vec::new();
+
+    for binding in bindings {
+        let (symbol, value) = binding_pair(environment, binding)?;
+
+        if environment.is_constant(&symbol) {
+            return Err(EvalError::ConstantReassigned(symbol.to_string()));
+        }
+
+        values.push((symbol, value));
+    }
+
+    let mut saved: Vec<(Symbol, Option<Value>)> = Vec::new();
+
+    for (symbol, value) in values {
+        saved.push((symbol.clone(), environment.top_level().get(&symbol)));
+        environment.top_level().put(symbol, value);
+    }
+
+    let result = progn(environment, args[1..].to_vec());
+
+    // Restore in reverse, so the first value saved for a symbol bound twice wins.
+    for (symbol, value) in saved.into_iter().rev() {
+        match value {
+            Some(value) => environment.top_level().put(symbol, value),
+            None => {
+                environment.top_level().remove(&symbol);
+            }
+        }
+    }
+
+    result
+}
+
+/// Evaluate the body in a scope of its own, so that `let` bindings in it don't outlive it.
+fn block(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    environment.push_to_stack(&"block".into());
+
+    let result = progn(environment, args);
+
+    environment.pop();
+
+    result
+}
+
+/// Evaluate a `[symbol value]` binding.
+fn binding_pair(
+    environment: &mut Environment,
+    binding: Value,
+) -> Result<(Symbol, Value), EvalError> {
+    match binding {
+        Value::List(pair) if pair.len() == 2 => match &pair[0] {
+            Value::Symbol(symbol) => Ok((symbol.unquoted(), pair[1].eval(environment)?)),
+            _ => mismatch(environment, "Binding names must be symbols"),
+        },
+        _ => mismatch(environment, "Bindings must be [symbol value] pairs"),
+    }
+}
+
+fn bind_sequentially(environment: &mut Environment, bindings: Vec<Value>) -> Result<(), EvalError> {
+    for binding in bindings {
+        let (symbol, value) = binding_pair(environment, binding)?;
+
+        environment.current().put(symbol, value);
+    }
+
+    Ok(())
+}
+
+fn step(environment: &mut Environment, args: Vec<Value>, sign: Integer) -> EvalResult {
+    if args.is_empty() || args.len() > 2 {
+        return mismatch(
+            environment,
+            "This function takes a symbol and an optional amount",
+        );
+    }
+
+    let symbol = match &args[0] {
+        Value::Symbol(symbol) => symbol.unquoted(),
+        _ => return mismatch(environment, "First argument must be a symbol"),
+    };
+
+    let amount = match args.get(1) {
+        Some(value) => match to_integer(value.eval(environment)?) {
+            Some(amount) => amount,
+            None => return mismatch(environment, "The amount must be an integer"),
+        },
+        None => 1,
+    };
+
+    let current = match environment.lookup(&symbol) {
+        Some(Value::Integer(current)) => current,
+        Some(_) => return mismatch(environment, "The variable must hold an integer"),
+        None => return Err(EvalError::VariableIsVoid(symbol.to_string())),
+    };
+
+    let amount = checked(environment, amount.checked_mul(sign))?;
+    let value = Value::Integer(checked(environment, current.checked_add(amount))?);
+
+    environment.assign(symbol, value.clone())?;
+
+    Ok(value)
+}
+
+fn incf(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    step(environment, args, 1)
+}
+
+fn decf(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    step(environment, args, -1)
+}
+
+/// Numbers are equal by value, so that `(= 1 1.0)` holds. Anything else has to be identical.
+fn equal(x: &Value, y: &Value) -> bool {
+    let number = |value: &Value| match value {
+        Value::Integer(_) | Value::Ratio(..) | Value::Float(_) => to_number(value.clone()),
+        #[cfg(feature = "bignum")]
+        Value::BigInt(_) => to_number(value.clone()),
+        _ => None,
+    };
+
+    match (number(x), number(y)) {
+        (Some(x), Some(y)) => x.compare(&y) == Some(Ordering::Equal),
+        _ => x == y,
+    }
+}
+
+/// Whether all args are equal to the first one. Stops evaluating them at the first that isn't.
+fn eq(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let args = some_args(environment, args)?;
+    let first = args[0].eval(environment)?;
+
+    for arg in args[1..].iter() {
+        if !equal(&first, &arg.eval(environment)?) {
+            return Ok(Value::Nil);
+        }
+    }
+
+    Ok(Value::T)
+}
+
+/// Whether no two args are equal.
+fn neq(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let args = some_args(environment, args)?;
+    let mut values: Vec<Value> = Vec::new();
+
+    for arg in args.iter() {
+        let value = arg.eval(environment)?;
+
+        if values.iter().any(|other| equal(other, &value)) {
+            return Ok(Value::Nil);
+        }
+
+        values.push(value);
+    }
+
+    Ok(Value::T)
+}
+
+fn to_integer(value: Value) -> Option<Integer> {
+    match value {
+        Value::Integer(i) => Some(i),
+        _ => None,
+    }
+}
+
+#[derive(Clone)]
+enum Number {
+    Integer(Integer),
+    #[cfg(feature = "bignum")]
+    Big(BigInt),
+    Ratio(Integer, Integer),
+    Float(Float),
+}
+
+impl Number {
+    /// An integer, demoted to `Integer` if it fits.
+    #[cfg(feature = "bignum")]
+    fn big(i: BigInt) -> Self {
+        match Value::big(i) {
+            Value::Integer(i) => Number::Integer(i),
+            Value::BigInt(i) => Number::Big(i),
+            _ => unreachable!(),
+        }
+    }
+
+    /// A fraction in lowest terms, or `None` if it doesn't fit.
+    fn ratio(numerator: i128, denominator: i128) -> Option<Self> {
+        Value::ratio(numerator, denominator).and_then(to_number)
+    }
+
+    /// The numerator and denominator of an exact number that isn't a bigint.
+    fn to_ratio(&self) -> Option<(i128, i128)> {
+        match self {
+            Number::Integer(i) => Some((*i as i128, 1)),
+            Number::Ratio(numerator, denominator) => {
+                Some((*numerator as i128, *denominator as i128))
+            }
+            _ => None,
+        }
+    }
+
+    /// Floats are truncated.
+    #[cfg(feature = "bignum")]
+    fn to_big(&self) -> BigInt {
+        match self {
+            Number::Integer(i) => BigInt::from(*i),
+            Number::Big(i) => i.clone(),
+            Number::Ratio(..) => BigInt::from_f64(self.to_float()).unwrap_or_default(),
+            Number::Float(x) => BigInt::from_f64(*x).unwrap_or_default(),
+        }
+    }
+
+    fn to_float(&self) -> Float {
+        match self {
+            Number::Integer(i) => *i as Float,
+            #[cfg(feature = "bignum")]
+            Number::Big(i) => i.to_f64().unwrap_or(Float::NAN),
+            Number::Ratio(numerator, denominator) => *numerator as Float / *denominator as Float,
+            Number::Float(x) => *x,
+        }
+    }
+
+    /// The number with its sign flipped, or `None` if that overflows.
+    fn negated(self) -> Option<Self> {
+        match self {
+            Number::Integer(i) => match i.checked_neg() {
+                Some(negated) => Some(Number::Integer(negated)),
+                #[cfg(feature = "bignum")]
+                None => Some(Number::big(-BigInt::from(i))),
+                #[cfg(not(feature = "bignum"))]
+                None => None,
+            },
+            #[cfg(feature = "bignum")]
+            Number::Big(i) => Some(Number::big(-i)),
+            Number::Ratio(numerator, denominator) => {
+                Some(Number::Ratio(numerator.checked_neg()?, denominator))
+            }
+            Number::Float(x) => Some(Number::Float(-x)),
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.to_float() == 0.0
+    }
+
+    fn compare(&self, other: &Number) -> Option<Ordering> {
+        match (self, other) {
+            (Number::Integer(x), Number::Integer(y)) => Some(x.cmp(y)),
+            (Number::Float(_), _) | (_, Number::Float(_)) => {
+                self.to_float().partial_cmp(&other.to_float())
+            }
+            (Number::Ratio(..), _) | (_, Number::Ratio(..)) => {
+                match (self.to_ratio(), other.to_ratio()) {
+                    (Some((n1, d1)), Some((n2, d2))) => Some((n1 * d2).cmp(&(n2 * d1))),
+                    _ => self.to_float().partial_cmp(&other.to_float()),
+                }
+            }
+            #[cfg(feature = "bignum")]
+            _ => Some(self.to_big().cmp(&other.to_big())),
+        }
+    }
+}
+
+impl From<Number> for Value {
+    fn from(number: Number) -> Self {
+        match number {
+            Number::Integer(i) => Value::Integer(i),
+            #[cfg(feature = "bignum")]
+            Number::Big(i) => Value::BigInt(i),
+            Number::Ratio(numerator, denominator) => Value::Ratio(numerator, denominator),
+            Number::Float(x) => Value::Float(x),
+        }
+    }
+}
+
+fn to_number(value: Value) -> Option<Number> {
+    match value {
+        Value::Integer(i) => Some(Number::Integer(i)),
+        #[cfg(feature = "bignum")]
+        Value::BigInt(i) => Some(Number::Big(i)),
+        Value::Ratio(numerator, denominator) => Some(Number::Ratio(numerator, denominator)),
+        Value::Float(x) => Some(Number::Float(x)),
+        _ => None,
+    }
+}
+
+/// The arithmetic `numeric` applies.
+#[derive(Clone, Copy)]
+enum Operation {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl Operation {
+    fn integers(self, x: Integer, y: Integer) -> Option<Integer> {
+        match self {
+            Operation::Add => x.checked_add(y),
+            Operation::Sub => x.checked_sub(y),
+            Operation::Mul => x.checked_mul(y),
+            Operation::Div => x.checked_div(y),
+        }
+    }
+
+    #[cfg(feature = "bignum")]
+    fn bigints(self, x: BigInt, y: BigInt) -> BigInt {
+        match self {
+            Operation::Add => x + y,
+            Operation::Sub => x - y,
+            Operation::Mul => x * y,
+            Operation::Div => x / y,
+        }
+    }
+
+    fn ratios(self, (n1, d1): (i128, i128), (n2, d2): (i128, i128)) -> Option<(i128, i128)> {
+        let cross = || Some((n1.checked_mul(d2)?, n2.checked_mul(d1)?));
+
+        match self {
+            Operation::Add => {
+                let (x, y) = cross()?;
+                Some((x.checked_add(y)?, d1.checked_mul(d2)?))
+            }
+            Operation::Sub => {
+                let (x, y) = cross()?;
+                Some((x.checked_sub(y)?, d1.checked_mul(d2)?))
+            }
+            Operation::Mul => Some((n1.checked_mul(n2)?, d1.checked_mul(d2)?)),
+            Operation::Div => cross(),
+        }
+    }
+
+    fn floats(self, x: Float, y: Float) -> Float {
+        match self {
+            Operation::Add => x + y,
+            Operation::Sub => x - y,
+            Operation::Mul => x * y,
+            Operation::Div => x / y,
+        }
+    }
+}
+
+fn inexact(
+    environment: &mut Environment,
+    x: &Number,
+    y: &Number,
+    operation: Operation,
+) -> Result<Number, EvalError> {
+    match operation.floats(x.to_float(), y.to_float()) {
+        result if result.is_finite() => Ok(Number::Float(result)),
+        _ => arithmetic_overflow(environment),
+    }
+}
+
+/// Apply an arithmetic operation, promoting to float if either operand is one, and to a ratio if
+/// either is one. Integers that overflow are promoted to bigints with the `bignum` feature.
+fn numeric(
+    environment: &mut Environment,
+    x: Number,
+    y: Number,
+    operation: Operation,
+) -> Result<Number, EvalError> {
+    match (&x, &y) {
+        (Number::Float(_), _) | (_, Number::Float(_)) => inexact(environment, &x, &y, operation),
+        (Number::Ratio(..), _) | (_, Number::Ratio(..)) => match (x.to_ratio(), y.to_ratio()) {
+            (Some(x), Some(y)) => {
+                let result = operation.ratios(x, y);
+                checked(environment, result.and_then(|(n, d)| Number::ratio(n, d)))
+            }
+            // Mixed with a bigint.
+            _ => inexact(environment, &x, &y, operation),
+        },
+        (Number::Integer(i), Number::Integer(j)) => match operation.integers(*i, *j) {
+            Some(result) => Ok(Number::Integer(result)),
+            #[cfg(feature = "bignum")]
+            None => Ok(Number::big(operation.bigints(x.to_big(), y.to_big()))),
+            #[cfg(not(feature = "bignum"))]
+            None => arithmetic_overflow(environment),
+        },
+        #[cfg(feature = "bignum")]
+        _ => Ok(Number::big(operation.bigints(x.to_big(), y.to_big()))),
+    }
+}
+
+fn ordered(
+    environment: &mut Environment,
+    args: Vec<Value>,
+    predicate: fn(Ordering) -> bool,
+) -> EvalResult {
+    if args.len() < 2 {
+        return mismatch(environment, "This function takes two or more numbers");
+    }
+
+    let mut numbers: Vec<Number> = Vec::new();
+
+    for arg in args.iter() {
+        match to_number(arg.eval(environment)?) {
+            Some(number) => numbers.push(number),
+            None => {
+                return mismatch(
+                    environment,
+                    format!("Couldn't convert argument {:?}", arg).as_str(),
+                )
+            }
         }
+    }
+
+    Ok(numbers
+        .windows(2)
+        .all(|pair| pair[0].compare(&pair[1]).is_some_and(predicate))
+        .into())
+}
+
+fn lt(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    ordered(environment, args, Ordering::is_lt)
+}
+
+fn gt(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    ordered(environment, args, Ordering::is_gt)
+}
+
+fn le(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    ordered(environment, args, Ordering::is_le)
+}
+
+fn ge(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    ordered(environment, args, Ordering::is_ge)
+}
+
+fn add(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let args = some_args(environment, args)?;
+
+    Ok(try_reduce(
+        environment,
+        Value::Integer(0),
+        args,
+        to_number,
+        |environment, x, y| numeric(environment, x, y, Operation::Add),
+    )?
+    .into())
+}
+
+fn sub(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() == 1 {
+        let negated = match to_number(args[0].eval(environment)?) {
+            Some(number) => checked(environment, number.negated())?,
+            None => return mismatch(environment, "This function takes one or more numbers"),
+        };
+
+        Ok(negated.into())
     } else {
-        Ok(Value::Integer(reduce_car_cdr(
+        Ok(
+            try_reduce_car_cdr(environment, args, to_number, |environment, x, y| {
+                numeric(environment, x, y, Operation::Sub)
+            })?
+            .into(),
+        )
+    }
+}
+
+fn mul(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let args = some_args(environment, args)?;
+
+    Ok(try_reduce(
+        environment,
+        Value::Integer(1),
+        args,
+        to_number,
+        |environment, x, y| numeric(environment, x, y, Operation::Mul),
+    )?
+    .into())
+}
+
+fn div(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    Ok(
+        try_reduce_car_cdr(environment, args, to_number, |environment, x, y| {
+            match y.is_zero() {
+                true => division_by_zero(environment),
+                false => numeric(environment, x, y, Operation::Div),
+            }
+        })?
+        .into(),
+    )
+}
+
+/// Remainder taking the sign of the divisor, as in other Lisps.
+fn modulo(x: Integer, y: Integer) -> Option<Integer> {
+    let remainder = x.checked_rem(y)?;
+
+    if remainder != 0 && (remainder < 0) != (y < 0) {
+        remainder.checked_add(y)
+    } else {
+        Some(remainder)
+    }
+}
+
+fn greatest_common_divisor(x: Integer, y: Integer) -> Option<Integer> {
+    let (mut x, mut y) = (x.checked_abs()?, y.checked_abs()?);
+
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+
+    Some(x)
+}
+
+fn least_common_multiple(x: Integer, y: Integer) -> Option<Integer> {
+    match greatest_common_divisor(x, y)? {
+        0 => Some(0),
+        divisor => (x / divisor).checked_mul(y)?.checked_abs(),
+    }
+}
+
+/// The arithmetic `integral` applies, which only works on integers.
+#[derive(Clone, Copy)]
+enum IntegerOperation {
+    Mod,
+    Gcd,
+    Lcm,
+}
+
+impl IntegerOperation {
+    fn integers(self, x: Integer, y: Integer) -> Option<Integer> {
+        match self {
+            IntegerOperation::Mod => modulo(x, y),
+            IntegerOperation::Gcd => greatest_common_divisor(x, y),
+            IntegerOperation::Lcm => least_common_multiple(x, y),
+        }
+    }
+
+    #[cfg(feature = "bignum")]
+    fn bigints(self, x: BigInt, y: BigInt) -> BigInt {
+        match self {
+            IntegerOperation::Mod => x.mod_floor(&y),
+            IntegerOperation::Gcd => x.gcd(&y),
+            IntegerOperation::Lcm => x.lcm(&y),
+        }
+    }
+}
+
+/// Apply an operation that only takes integers. Ones that overflow are promoted to bigints with
+/// the `bignum` feature.
+fn integral(
+    environment: &mut Environment,
+    x: Number,
+    y: Number,
+    operation: IntegerOperation,
+) -> Result<Number, EvalError> {
+    match (&x, &y) {
+        (Number::Integer(i), Number::Integer(j)) => match operation.integers(*i, *j) {
+            Some(result) => Ok(Number::Integer(result)),
+            #[cfg(feature = "bignum")]
+            None => Ok(Number::big(operation.bigints(x.to_big(), y.to_big()))),
+            #[cfg(not(feature = "bignum"))]
+            None => arithmetic_overflow(environment),
+        },
+        #[cfg(feature = "bignum")]
+        (Number::Integer(_) | Number::Big(_), Number::Integer(_) | Number::Big(_)) => {
+            Ok(Number::big(operation.bigints(x.to_big(), y.to_big())))
+        }
+        _ => mismatch(environment, "This function takes integers"),
+    }
+}
+
+fn mod_(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    Ok(
+        try_reduce_car_cdr(environment, args, to_number, |environment, x, y| {
+            match y.is_zero() {
+                true => division_by_zero(environment),
+                false => integral(environment, x, y, IntegerOperation::Mod),
+            }
+        })?
+        .into(),
+    )
+}
+
+/// How `floor` and friends turn a quotient into an integer.
+#[derive(Clone, Copy)]
+enum Rounding {
+    Floor,
+    Ceiling,
+    /// To the nearest integer, and to the even one on ties.
+    Round,
+    Truncate,
+}
+
+impl Rounding {
+    /// Round `numerator / denominator`, where the denominator is positive.
+    fn integers(self, numerator: i128, denominator: i128) -> i128 {
+        let quotient = numerator / denominator;
+        let remainder = numerator % denominator;
+        let away = if remainder < 0 { -1 } else { 1 };
+
+        let round_away = match self {
+            Rounding::Floor => remainder < 0,
+            Rounding::Ceiling => remainder > 0,
+            Rounding::Round => {
+                let twice = 2 * remainder.abs();
+                twice > denominator || (twice == denominator && quotient % 2 != 0)
+            }
+            Rounding::Truncate => false,
+        };
+
+        if round_away {
+            quotient + away
+        } else {
+            quotient
+        }
+    }
+
+    /// Like `integers`, for quotients too large for them.
+    #[cfg(feature = "bignum")]
+    fn bigints(self, numerator: &BigInt, denominator: &BigInt) -> BigInt {
+        let (quotient, remainder) = numerator.div_rem(denominator);
+        let away = if remainder.is_negative() { -1 } else { 1 };
+
+        let round_away = match self {
+            Rounding::Floor => remainder.is_negative(),
+            Rounding::Ceiling => remainder.is_positive(),
+            Rounding::Round => {
+                let twice = remainder.abs() * 2;
+                twice > *denominator || (twice == *denominator && quotient.is_odd())
+            }
+            Rounding::Truncate => false,
+        };
+
+        if round_away {
+            quotient + away
+        } else {
+            quotient
+        }
+    }
+
+    fn floats(self, x: Float) -> Float {
+        match self {
+            Rounding::Floor => x.floor(),
+            Rounding::Ceiling => x.ceil(),
+            Rounding::Round => x.round_ties_even(),
+            Rounding::Truncate => x.trunc(),
+        }
+    }
+}
+
+/// Divide a number by an optional divisor, returning the rounded quotient as an integer and the
+/// remainder as a second value.
+fn rounded_division(
+    environment: &mut Environment,
+    args: Vec<Value>,
+    rounding: Rounding,
+) -> EvalResult {
+    if args.is_empty() || args.len() > 2 {
+        return mismatch(
+            environment,
+            "This function takes a number and an optional divisor",
+        );
+    }
+
+    let mut numbers: Vec<Number> = Vec::new();
+
+    for arg in args.iter() {
+        match to_number(arg.eval(environment)?) {
+            Some(number) => numbers.push(number),
+            None => return mismatch(environment, "This function takes numbers"),
+        }
+    }
+
+    let x = numbers.remove(0);
+    let y = numbers.pop().unwrap_or(Number::Integer(1));
+
+    if y.is_zero() {
+        return division_by_zero(environment);
+    }
+
+    let (quotient, remainder) = match (x.to_ratio(), y.to_ratio()) {
+        (Some((n1, d1)), Some((n2, d2))) => {
+            // x / y = (n1 * d2) / (d1 * n2), with the sign moved to the numerator.
+            let sign = n2.signum();
+            let quotient = rounding.integers(n1 * d2 * sign, d1 * n2 * sign);
+
+            // x - quotient * y = (n1 * d2 - quotient * n2 * d1) / (d1 * d2)
+            let remainder = quotient
+                .checked_mul(n2)
+                .and_then(|product| product.checked_mul(d1))
+                .and_then(|product| (n1 * d2).checked_sub(product))
+                .and_then(|numerator| Number::ratio(numerator, d1 * d2));
+
+            match (Integer::try_from(quotient), remainder) {
+                (Ok(quotient), Some(remainder)) => (Number::Integer(quotient), remainder),
+                #[cfg(feature = "bignum")]
+                _ if d1 == 1 && d2 == 1 => rounded_bigints(&x, &y, rounding),
+                _ => return arithmetic_overflow(environment),
+            }
+        }
+        #[cfg(feature = "bignum")]
+        _ if matches!(
+            (&x, &y),
+            (
+                Number::Integer(_) | Number::Big(_),
+                Number::Integer(_) | Number::Big(_)
+            )
+        ) =>
+        {
+            rounded_bigints(&x, &y, rounding)
+        }
+        _ => {
+            let (x, y) = (x.to_float(), y.to_float());
+            let quotient = rounding.floats(x / y);
+            let remainder = Number::Float(x - quotient * y);
+
+            // Casts saturate, so anything at either end doesn't fit.
+            match quotient {
+                _ if quotient.is_finite() && quotient.abs() < Integer::MAX as Float => {
+                    (Number::Integer(quotient as Integer), remainder)
+                }
+                #[cfg(feature = "bignum")]
+                _ if quotient.is_finite() => {
+                    (Number::big(BigInt::from_f64(quotient).unwrap()), remainder)
+                }
+                _ => return arithmetic_overflow(environment),
+            }
+        }
+    };
+
+    Ok(Value::Values(vec![quotient.into(), remainder.into()]))
+}
+
+/// Divide bigints like `rounded_division`, returning the quotient and the remainder.
+#[cfg(feature = "bignum")]
+fn rounded_bigints(x: &Number, y: &Number, rounding: Rounding) -> (Number, Number) {
+    let (x, y) = (x.to_big(), y.to_big());
+    let sign = y.signum();
+    let quotient = rounding.bigints(&(&x * &sign), &(&y * &sign));
+    let remainder = x - &quotient * y;
+
+    (Number::big(quotient), Number::big(remainder))
+}
+
+fn floor(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    rounded_division(environment, args, Rounding::Floor)
+}
+
+fn ceiling(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    rounded_division(environment, args, Rounding::Ceiling)
+}
+
+fn round(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    rounded_division(environment, args, Rounding::Round)
+}
+
+fn truncate(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    rounded_division(environment, args, Rounding::Truncate)
+}
+
+/// Pick the number that `wins` against all others, keeping the earlier one on ties.
+fn extremum(environment: &mut Environment, args: Vec<Value>, wins: Ordering) -> EvalResult {
+    let args = some_args(environment, args)?;
+
+    Ok(
+        reduce_car_cdr(environment, args, to_number, |x, y| match y.compare(&x) {
+            Some(ordering) if ordering == wins => y,
+            _ => x,
+        })?
+        .into(),
+    )
+}
+
+fn min(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    extremum(environment, args, Ordering::Less)
+}
+
+fn max(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    extremum(environment, args, Ordering::Greater)
+}
+
+fn abs(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let absolute = match to_number(single_arg(environment, args)?) {
+        Some(Number::Float(x)) => Some(Number::Float(x.abs())),
+        Some(number) if number.compare(&Number::Integer(0)) == Some(Ordering::Less) => {
+            number.negated()
+        }
+        Some(number) => Some(number),
+        None => return mismatch(environment, "This function takes a number"),
+    };
+
+    Ok(checked(environment, absolute)?.into())
+}
+
+fn expt(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() != 2 {
+        return mismatch(environment, "This function takes a base and an exponent");
+    }
+
+    let base = to_number(args[0].eval(environment)?);
+    let exponent = match to_integer(args[1].eval(environment)?) {
+        Some(exponent) if exponent >= 0 => exponent,
+        _ => {
+            return mismatch(
+                environment,
+                "This function takes an integer base and a non-negative exponent",
+            )
+        }
+    };
+
+    match base {
+        Some(Number::Integer(base)) => {
+            let exponent = checked(environment, u32::try_from(exponent).ok())?;
+
+            match base.checked_pow(exponent) {
+                Some(power) => Ok(Value::Integer(power)),
+                #[cfg(feature = "bignum")]
+                None => Ok(Value::big(BigInt::from(base).pow(exponent))),
+                #[cfg(not(feature = "bignum"))]
+                None => arithmetic_overflow(environment),
+            }
+        }
+        #[cfg(feature = "bignum")]
+        Some(Number::Big(base)) => {
+            let exponent = checked(environment, u32::try_from(exponent).ok())?;
+            Ok(Value::big(base.pow(exponent)))
+        }
+        _ => mismatch(
+            environment,
+            "This function takes an integer base and a non-negative exponent",
+        ),
+    }
+}
+
+fn gcd(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    Ok(try_reduce(
+        environment,
+        Value::Integer(0),
+        args,
+        to_number,
+        |environment, x, y| integral(environment, x, y, IntegerOperation::Gcd),
+    )?
+    .into())
+}
+
+fn lcm(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    Ok(try_reduce(
+        environment,
+        Value::Integer(1),
+        args,
+        to_number,
+        |environment, x, y| integral(environment, x, y, IntegerOperation::Lcm),
+    )?
+    .into())
+}
+
+fn bitwise(
+    environment: &mut Environment,
+    args: Vec<Value>,
+    identity: Integer,
+    operation: fn(Integer, Integer) -> Integer,
+) -> EvalResult {
+    Ok(Value::Integer(try_reduce(
+        environment,
+        Value::Integer(identity),
+        args,
+        to_integer,
+        |_, x, y| Ok(operation(x, y)),
+    )?))
+}
+
+fn logand(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    bitwise(environment, args, -1, |x, y| x & y)
+}
+
+fn logior(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    bitwise(environment, args, 0, |x, y| x | y)
+}
+
+fn logxor(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    bitwise(environment, args, 0, |x, y| x ^ y)
+}
+
+fn lognot(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    match to_integer(single_arg(environment, args)?) {
+        Some(i) => Ok(Value::Integer(!i)),
+        None => mismatch(environment, "This function takes an integer"),
+    }
+}
+
+/// Arithmetic shift: left for a positive count, right for a negative one.
+fn shift(mut number: Integer, count: Integer) -> Option<Integer> {
+    if count < 0 {
+        return Some(number >> count.unsigned_abs().min((Integer::BITS - 1).into()));
+    }
+
+    for _ in 0..count {
+        if number == 0 {
+            break;
+        }
+
+        number = number.checked_mul(2)?;
+    }
+
+    Some(number)
+}
+
+fn ash(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() != 2 {
+        return mismatch(
+            environment,
+            "This function takes an integer and a shift count",
+        );
+    }
+
+    let number = to_integer(args[0].eval(environment)?);
+    let count = to_integer(args[1].eval(environment)?);
+
+    match (number, count) {
+        (Some(number), Some(count)) => {
+            Ok(Value::Integer(checked(environment, shift(number, count))?))
+        }
+        _ => mismatch(
+            environment,
+            "This function takes an integer and a shift count",
+        ),
+    }
+}
+
+fn car(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    match list_arg(environment, args)? {
+        Value::List(elements) | Value::Dotted(elements, _) => {
+            Ok(elements.into_iter().next().unwrap_or(Value::Nil))
+        }
+        _ => mismatch(environment, "This function takes a list"),
+    }
+}
+
+fn cdr(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    match list_arg(environment, args)? {
+        Value::List(elements) => Ok(Value::List(elements.into_iter().skip(1).collect())),
+        Value::Dotted(elements, tail) => {
+            Ok(Value::dotted(elements.into_iter().skip(1).collect(), *tail))
+        }
+        _ => mismatch(environment, "This function takes a list"),
+    }
+}
+
+fn cons(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() != 2 {
+        return mismatch(environment, "This function takes a car and a cdr");
+    }
+
+    let car = args[0].eval(environment)?;
+    let cdr = args[1].eval(environment)?;
+
+    Ok(Value::dotted(vec![car], cdr))
+}
+
+fn list(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    Value::List(args).eval(environment)
+}
+
+fn length(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let length = match single_arg(environment, args)? {
+        Value::Nil => 0,
+        Value::List(elements) => elements.len(),
+        Value::String(string) => string.chars().count(),
+        Value::Dotted(..) => {
+            return mismatch(
+                environment,
+                "This function takes a proper list, not an improper list",
+            )
+        }
+        _ => return mismatch(environment, "This function takes a list or a string"),
+    };
+
+    Ok(Value::Integer(checked(
+        environment,
+        length.try_into().ok(),
+    )?))
+}
+
+fn reverse(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let value = single_arg(environment, args)?;
+    let mut elements = elements(environment, value)?;
+
+    elements.reverse();
+
+    Ok(Value::List(elements))
+}
+
+fn append(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let mut appended: Vec<Value> = Vec::new();
+
+    for arg in args {
+        let value = arg.eval(environment)?;
+        appended.extend(elements(environment, value)?);
+    }
+
+    Ok(Value::List(appended))
+}
+
+fn last(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let value = single_arg(environment, args)?;
+    let elements = elements(environment, value)?;
+
+    Ok(elements.last().cloned().unwrap_or(Value::Nil))
+}
+
+/// The tail of a list starting at the first element that is `=` to an item.
+fn member(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() != 2 {
+        return mismatch(environment, "This function takes an element and a list");
+    }
+
+    let target = args[0].eval(environment)?;
+    let list = args[1].eval(environment)?;
+    let elements = elements(environment, list)?;
+
+    match elements.iter().position(|element| equal(element, &target)) {
+        Some(index) => Ok(Value::List(elements[index..].to_vec())),
+        None => Ok(Value::Nil),
+    }
+}
+
+/// Find the first element of a list, or character of a string, that is `=` to an item.
+fn search(
+    environment: &mut Environment,
+    args: Vec<Value>,
+) -> Result<Option<(usize, Value)>, EvalError> {
+    if args.len() != 2 {
+        return mismatch(
+            environment,
+            "This function takes an item and a list or string",
+        );
+    }
+
+    let target = args[0].eval(environment)?;
+
+    let elements = match args[1].eval(environment)? {
+        Value::String(string) => string.chars().map(Value::Char).collect(),
+        sequence => elements(environment, sequence)?,
+    };
+
+    Ok(elements
+        .into_iter()
+        .enumerate()
+        .find(|(_, element)| equal(element, &target)))
+}
+
+fn position(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    match search(environment, args)? {
+        Some((index, _)) => Ok(Value::Integer(index as Integer)),
+        None => Ok(Value::Nil),
+    }
+}
+
+fn find(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    match search(environment, args)? {
+        Some((_, element)) => Ok(element),
+        None => Ok(Value::Nil),
+    }
+}
+
+/// The first pair in a list whose key is `=` to the one given.
+fn assoc(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() != 2 {
+        return mismatch(environment, "This function takes a key and a list of pairs");
+    }
+
+    let key = args[0].eval(environment)?;
+    let list = args[1].eval(environment)?;
+
+    for pair in elements(environment, list)? {
+        if let Value::List(entry) = &pair {
+            if entry.first().is_some_and(|first| equal(first, &key)) {
+                return Ok(pair);
+            }
+        }
+    }
+
+    Ok(Value::Nil)
+}
+
+fn element_at(environment: &mut Environment, index: Value, list: Value) -> EvalResult {
+    let index = match index {
+        // Too large to fit is past the end all the same.
+        Value::Integer(i) if i >= 0 => usize::try_from(i).unwrap_or(usize::MAX),
+        Value::Integer(_) => return mismatch(environment, "The index must not be negative"),
+        _ => return mismatch(environment, "The index must be an integer"),
+    };
+
+    match list {
+        Value::Nil => Ok(Value::Nil),
+        Value::List(elements) => Ok(elements.get(index).cloned().unwrap_or(Value::Nil)),
+        _ => mismatch(environment, "This function takes a list"),
+    }
+}
+
+fn string_arg(environment: &mut Environment, arg: &Value) -> Result<String, EvalError> {
+    match arg.eval(environment)? {
+        Value::String(string) => Ok(string),
+        value => mismatch(
+            environment,
+            format!("Expected a string, got {}", value).as_str(),
+        ),
+    }
+}
+
+fn concat(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let mut result = String::new();
+
+    for arg in args.iter() {
+        result.push_str(&string_arg(environment, arg)?);
+    }
+
+    Ok(Value::String(result))
+}
+
+/// Fill `~a` (strings printed raw) and `~s` (printed as source) placeholders; `~~` is a tilde.
+fn format(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let template = match args.first() {
+        Some(template) => string_arg(environment, template)?,
+        None => return mismatch(environment, "This function takes a format string"),
+    };
+
+    let mut values = args[1..].iter();
+    let mut result = String::new();
+    let mut characters = template.chars();
+
+    while let Some(character) = characters.next() {
+        if character != '~' {
+            result.push(character);
+            continue;
+        }
+
+        let directive = characters.next();
+
+        if directive == Some('~') {
+            result.push('~');
+            continue;
+        }
+
+        let value = match values.next() {
+            Some(value) => value.eval(environment)?,
+            None => return mismatch(environment, "Not enough args for the format string"),
+        };
+
+        match (directive, value) {
+            (Some('a'), value) => result.push_str(&to_raw_string(&value)),
+            (Some('s'), value) => result.push_str(&value.to_string()),
+            _ => return mismatch(environment, "Unknown format directive"),
+        }
+    }
+
+    if values.next().is_some() {
+        return mismatch(environment, "Too many args for the format string");
+    }
+
+    Ok(Value::String(result))
+}
+
+fn index_arg(environment: &mut Environment, arg: &Value) -> Result<usize, EvalError> {
+    match arg.eval(environment)? {
+        Value::Integer(i) if i >= 0 => Ok(usize::try_from(i).unwrap_or(usize::MAX)),
+        Value::Integer(_) => mismatch(environment, "The index must not be negative"),
+        _ => mismatch(environment, "The index must be an integer"),
+    }
+}
+
+fn substring(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() < 2 || args.len() > 3 {
+        return mismatch(
+            environment,
+            "This function takes a string, a start and an optional end",
+        );
+    }
+
+    let characters: Vec<char> = string_arg(environment, &args[0])?.chars().collect();
+    let start = index_arg(environment, &args[1])?;
+
+    let end = match args.get(2) {
+        Some(end) => index_arg(environment, end)?,
+        None => characters.len(),
+    };
+
+    match characters.get(start..end) {
+        Some(characters) => Ok(Value::String(characters.iter().collect())),
+        None => mismatch(
+            environment,
+            format!("Range {}..{} is out of bounds", start, end).as_str(),
+        ),
+    }
+}
+
+fn upcase(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let string = match args.as_slice() {
+        [string] => string_arg(environment, string)?,
+        _ => return mismatch(environment, "This function takes exactly one argument"),
+    };
+
+    Ok(Value::String(string.to_uppercase()))
+}
+
+fn downcase(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let string = match args.as_slice() {
+        [string] => string_arg(environment, string)?,
+        _ => return mismatch(environment, "This function takes exactly one argument"),
+    };
+
+    Ok(Value::String(string.to_lowercase()))
+}
+
+fn split(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() != 2 {
+        return mismatch(environment, "This function takes a string and a separator");
+    }
+
+    let string = string_arg(environment, &args[0])?;
+    let separator = string_arg(environment, &args[1])?;
+
+    if separator.is_empty() {
+        return mismatch(environment, "The separator must not be empty");
+    }
+
+    Ok(Value::List(
+        string
+            .split(separator.as_str())
+            .map(|part| Value::String(part.to_string()))
+            .collect(),
+    ))
+}
+
+fn char_at(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() != 2 {
+        return mismatch(environment, "This function takes a string and an index");
+    }
+
+    let string = string_arg(environment, &args[0])?;
+    let index = index_arg(environment, &args[1])?;
+
+    match string.chars().nth(index) {
+        Some(character) => Ok(Value::Char(character)),
+        None => mismatch(
+            environment,
+            format!("Index {} is out of bounds", index).as_str(),
+        ),
+    }
+}
+
+fn char_code(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    match single_arg(environment, args)? {
+        Value::Char(character) => Ok(Value::Integer(character as Integer)),
+        _ => mismatch(environment, "This function takes a character"),
+    }
+}
+
+fn code_char(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let code = match single_arg(environment, args)? {
+        Value::Integer(code) => code.try_into().ok().and_then(std::char::from_u32),
+        _ => return mismatch(environment, "This function takes an integer"),
+    };
+
+    match code {
+        Some(character) => Ok(Value::Char(character)),
+        None => mismatch(environment, "Not a valid character code"),
+    }
+}
+
+/// Evaluate a character, which may also be given by its code.
+fn character_arg(environment: &mut Environment, arg: &Value) -> Result<char, EvalError> {
+    let value = arg.eval(environment)?;
+
+    let character = match value {
+        Value::Char(character) => Some(character),
+        Value::Integer(code) => code.try_into().ok().and_then(std::char::from_u32),
+        _ => None,
+    };
+
+    match character {
+        Some(character) => Ok(character),
+        None => mismatch(
+            environment,
+            format!("Expected a character, got {}", value).as_str(),
+        ),
+    }
+}
+
+fn make_string(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() != 2 {
+        return mismatch(environment, "This function takes a count and a character");
+    }
+
+    let count = index_arg(environment, &args[0])?;
+    let character = character_arg(environment, &args[1])?;
+
+    // Check the size up front, since `repeat` panics if it overflows.
+    let mut result = String::new();
+    let reserved = match count.checked_mul(character.len_utf8()) {
+        Some(length) => result.try_reserve_exact(length).is_ok(),
+        None => false,
+    };
+
+    if !reserved {
+        return arithmetic_overflow(environment);
+    }
+
+    result.extend(std::iter::repeat_n(character, count));
+    Ok(Value::String(result))
+}
+
+fn string(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let mut result = String::new();
+
+    for arg in args.iter() {
+        result.push(character_arg(environment, arg)?);
+    }
+
+    Ok(Value::String(result))
+}
+
+fn to_string(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    Ok(Value::String(single_arg(environment, args)?.to_string()))
+}
+
+fn read(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() != 1 {
+        return mismatch(environment, "This function takes exactly one argument");
+    }
+
+    let source = string_arg(environment, &args[0])?;
+
+    parse(&source).map_err(EvalError::FailedToParse)
+}
+
+fn hash_key(environment: &mut Environment, value: Value) -> Result<HashKey, EvalError> {
+    match HashKey::new(value.clone()) {
+        Some(key) => Ok(key),
+        None => mismatch(
             environment,
-            args,
-            to_integer,
-            |x, y| x - y,
-        )?))
+            format!("Can't use {} as a key", value).as_str(),
+        ),
     }
 }
 
-fn mul(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
-    let args = some_args(environment, args)?;
+fn hash_map_arg(
+    environment: &mut Environment,
+    arg: &Value,
+) -> Result<HashMap<HashKey, Value>, EvalError> {
+    match arg.eval(environment)? {
+        Value::HashMap(map) => Ok(map),
+        value => mismatch(
+            environment,
+            format!("Expected a hash map, got {}", value).as_str(),
+        ),
+    }
+}
 
-    Ok(Value::Integer(reduce(
-        environment,
-        Value::Integer(1),
-        args,
-        to_integer,
-        |x, y| x * y,
-    )?))
+/// Evaluate a file in the current environment, returning the value of its last form.
+fn load(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let name = match args.as_slice() {
+        [name] => string_arg(environment, name)?,
+        _ => return mismatch(environment, "This function takes a file name"),
+    };
+
+    environment.eval_file(name)
 }
 
-fn div(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
-    Ok(Value::Integer(reduce_car_cdr(
-        environment,
-        args,
-        to_integer,
-        |x, y| x / y,
-    )?))
+/// Like `load`, but only the first time a file is required. Returns `t` if it was loaded.
+fn require(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let name = match args.as_slice() {
+        [name] => string_arg(environment, name)?,
+        _ => return mismatch(environment, "This function takes a file name"),
+    };
+
+    Ok(environment.require(name)?.into())
 }
 
-fn car(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
-    match list_arg(environment, args)? {
-        Value::List(elements) => elements.first().unwrap_or(&Value::Nil).eval(environment),
-        _ => mismatch(environment, "This function takes a list".into()),
+fn read_file(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let name = match args.as_slice() {
+        [name] => string_arg(environment, name)?,
+        _ => return mismatch(environment, "This function takes a file name"),
+    };
+
+    match std::fs::read_to_string(&name) {
+        Ok(contents) => Ok(Value::String(contents)),
+        Err(err) => Err(EvalError::FailedToReadFile(name, err)),
     }
 }
 
-fn cdr(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
-    match list_arg(environment, args)? {
-        Value::List(elements) => {
-            Value::List(elements.iter().cloned().skip(1).collect()).eval(environment)
-        }
-        _ => mismatch(environment, "This function takes a list".into()),
+/// Replace the contents of a file with a string, creating it if needed.
+fn write_file(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let (name, contents) = match args.as_slice() {
+        [name, contents] => (
+            string_arg(environment, name)?,
+            string_arg(environment, contents)?,
+        ),
+        _ => return mismatch(environment, "This function takes a file name and a string"),
+    };
+
+    match std::fs::write(&name, contents) {
+        Ok(()) => Ok(Value::T),
+        Err(err) => Err(EvalError::FailedToWriteFile(name, err)),
+    }
+}
+
+/// The value of an environment variable, or `nil` if it isn't set.
+fn getenv(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let name = match args.as_slice() {
+        [name] => string_arg(environment, name)?,
+        _ => return mismatch(environment, "This function takes a variable name"),
+    };
+
+    match std::env::var(name) {
+        Ok(value) => Ok(Value::String(value)),
+        Err(_) => Ok(Value::Nil),
+    }
+}
+
+/// The arguments given to the scripts after `--` on the command line.
+fn command_line_args(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if !args.is_empty() {
+        return mismatch(environment, "This function takes no arguments");
+    }
+
+    Ok(Value::List(
+        environment
+            .command_line_args()
+            .iter()
+            .cloned()
+            .map(Value::String)
+            .collect(),
+    ))
+}
+
+/// Seconds since the Unix epoch, with a fractional part for timing things.
+fn now(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if !args.is_empty() {
+        return mismatch(environment, "This function takes no arguments");
+    }
+
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => Ok(Value::Float(elapsed.as_secs_f64())),
+        Err(_) => mismatch(environment, "The system clock is set before the epoch"),
+    }
+}
+
+fn make_hash(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if !args.len().is_multiple_of(2) {
+        return mismatch(environment, "This function takes pairs of keys and values");
+    }
+
+    let mut map: HashMap<HashKey, Value> = HashMap::new();
+
+    for pair in args.chunks(2) {
+        let key = pair[0].eval(environment)?;
+        let key = hash_key(environment, key)?;
+
+        map.insert(key, pair[1].eval(environment)?);
+    }
+
+    Ok(Value::HashMap(map))
+}
+
+fn gethash(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() < 2 || args.len() > 3 {
+        return mismatch(
+            environment,
+            "This function takes a key, a hash map and an optional default",
+        );
+    }
+
+    let key = args[0].eval(environment)?;
+    let key = hash_key(environment, key)?;
+    let map = hash_map_arg(environment, &args[1])?;
+
+    match map.get(&key) {
+        Some(value) => Ok(value.clone()),
+        None => match args.get(2) {
+            Some(default) => default.eval(environment),
+            None => Ok(Value::Nil),
+        },
+    }
+}
+
+/// Return a copy of the hash map with the key set; the original is left as is.
+fn puthash(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() != 3 {
+        return mismatch(
+            environment,
+            "This function takes a key, a value and a hash map",
+        );
+    }
+
+    let key = args[0].eval(environment)?;
+    let key = hash_key(environment, key)?;
+    let value = args[1].eval(environment)?;
+    let mut map = hash_map_arg(environment, &args[2])?;
+
+    map.insert(key, value);
+
+    Ok(Value::HashMap(map))
+}
+
+fn nth(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() != 2 {
+        return mismatch(environment, "This function takes an index and a list");
+    }
+
+    let index = args[0].eval(environment)?;
+    let list = args[1].eval(environment)?;
+
+    element_at(environment, index, list)
+}
+
+fn elt(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() != 2 {
+        return mismatch(environment, "This function takes a list and an index");
+    }
+
+    let list = args[0].eval(environment)?;
+    let index = args[1].eval(environment)?;
+
+    element_at(environment, index, list)
+}
+
+fn gensym(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if !args.is_empty() {
+        return mismatch(environment, "This function takes no args");
+    }
+
+    Ok(Value::Symbol(environment.gensym()))
+}
+
+fn symbol_name(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    match single_arg(environment, args)? {
+        Value::Symbol(symbol) => Ok(Value::String(symbol.name)),
+        _ => mismatch(environment, "This function takes a symbol"),
+    }
+}
+
+fn intern(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    match single_arg(environment, args)? {
+        Value::String(name) if !name.is_empty() => Ok(Value::Symbol(Symbol::from_str(&name))),
+        Value::String(_) => mismatch(environment, "A symbol name must not be empty"),
+        _ => mismatch(environment, "This function takes a string"),
     }
 }
 
@@ -322,48 +2512,342 @@ fn defun(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
     if args.len() < 2 {
         return mismatch(
             environment,
-            "This function takes a function name, arg descriptor, and optional body".into(),
+            "This function takes a function name, arg descriptor, and optional body",
+        );
+    }
+
+    let name = match args.first().unwrap() {
+        Value::Symbol(symbol) => symbol,
+        _ => return mismatch(environment, "The first argument must be a symbol"),
+    };
+
+    let defun = make_defun(environment, &args[1], args[2..].to_vec())?;
+
+    environment.add_function(name.clone(), Function::Defun(defun));
+
+    Ok(Value::Nil)
+}
+
+/// Put functions defined from now on into a package, or back into the default one with `nil`.
+fn in_package(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    match single_arg(environment, args)? {
+        Value::Symbol(symbol) if symbol.package.is_none() => {
+            environment.set_package(Some(symbol.name.clone()));
+            Ok(Value::Symbol(symbol))
+        }
+        Value::Nil => {
+            environment.set_package(None);
+            Ok(Value::Nil)
+        }
+        _ => mismatch(environment, "This function takes a package name or nil"),
+    }
+}
+
+fn defmacro(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() < 2 {
+        return mismatch(
+            environment,
+            "This function takes a macro name, arg descriptor, and optional body",
         );
     }
 
     let name = match args.first().unwrap() {
         Value::Symbol(symbol) => symbol,
-        _ => return mismatch(environment, "The first argument must be a symbol".into()),
+        _ => return mismatch(environment, "The first argument must be a symbol"),
     };
 
-    let body = make_progn(args[2..].to_vec());
+    let transformer = make_defun(environment, &args[1], args[2..].to_vec())?;
 
+    environment.add_macro(name.clone(), transformer);
+
+    Ok(Value::Nil)
+}
+
+fn macroexpand(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let form = single_arg(environment, args)?;
+
+    if let Value::Funcall(head, args) = &form {
+        if let Value::Symbol(symbol) = head.as_ref() {
+            if let Some(expansion) = environment.macroexpand(symbol, args)? {
+                return Ok(expansion);
+            }
+        }
+    }
+
+    Ok(form)
+}
+
+fn lambda(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.is_empty() {
+        return mismatch(
+            environment,
+            "This function takes an arg descriptor and optional body",
+        );
+    }
+
+    let lambda = make_defun(environment, &args[0], args[1..].to_vec())?;
+
+    Ok(Value::Lambda(Box::new(
+        lambda.capturing(environment.capture()),
+    )))
+}
+
+/// The function named by a symbol as a value that can be passed around. Builtins are wrapped in
+/// a lambda applying them to its args.
+fn function(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let symbol = match args.as_slice() {
+        [Value::Symbol(symbol)] => symbol.unquoted(),
+        _ => return mismatch(environment, "This function takes a function name"),
+    };
+
+    match environment.function(&symbol) {
+        Some(Function::Defun(defun)) => Ok(Value::Lambda(Box::new(defun))),
+        Some(Function::Builtin(_)) => {
+            let rest = Symbol::new("args".into(), Quote::None, true);
+
+            let body = Value::Funcall(
+                Box::new(Value::Symbol(Symbol::from_str("apply"))),
+                vec![
+                    Value::Symbol(Symbol::new(symbol.name, Quote::Single, false)),
+                    Value::Symbol(Symbol::from_str("args")),
+                ],
+            );
+
+            Ok(Value::Lambda(Box::new(Defun::new(body, vec![rest]))))
+        }
+        None => Err(environment.void_function(&symbol)),
+    }
+}
+
+fn traced_function(environment: &mut Environment, args: Vec<Value>) -> Result<Symbol, EvalError> {
+    let symbol = match single_arg(environment, args)? {
+        Value::Symbol(symbol) => symbol.unquoted(),
+        _ => return mismatch(environment, "This function takes a function name"),
+    };
+
+    match environment.function(&symbol) {
+        Some(Function::Defun(_)) => Ok(symbol),
+        Some(Function::Builtin(_)) => mismatch(environment, "Only defuns can be traced"),
+        None => Err(environment.void_function(&symbol)),
+    }
+}
+
+fn trace(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let symbol = traced_function(environment, args)?;
+    environment.trace(&symbol);
+    Ok(Value::Symbol(symbol))
+}
+
+fn untrace(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let symbol = traced_function(environment, args)?;
+    Ok(Value::from(environment.untrace(&symbol)))
+}
+
+/// Build a function from an arg descriptor like `[x &optional [y default] rest...]`.
+fn make_defun(
+    environment: &mut Environment,
+    descriptor: &Value,
+    body: Vec<Value>,
+) -> Result<Defun, EvalError> {
     let mut takes: Vec<Symbol> = Vec::new();
+    let mut optional: Vec<(Symbol, Value)> = Vec::new();
+    let mut optional_marker = false;
 
-    let args_list = match args.get(1).unwrap() {
+    let args_list = match descriptor {
         Value::List(args) => args,
-        _ => {
-            return mismatch(
-                environment,
-                "The second argument must be a list of symbols".into(),
-            )
-        }
+        _ => return mismatch(environment, "The arg descriptor must be a list of symbols"),
     };
 
     for arg in args_list.iter() {
+        if takes.last().is_some_and(|last| last.rest) {
+            return mismatch(environment, "The rest arg must come last");
+        }
+
         match arg {
-            Value::Symbol(symbol) => {
-                match takes.last() {
-                    Some(next) => {
-                        if symbol.rest && next.rest {
-                            return mismatch(environment, "Only one rest arg is allowed");
-                        }
-                    }
-                    _ => {}
+            Value::Symbol(symbol) if symbol.name == OPTIONAL && symbol.quote == Quote::None => {
+                if optional_marker {
+                    return mismatch(environment, "Only one &optional marker is allowed");
                 }
 
-                takes.push(symbol.clone());
+                optional_marker = true;
             }
+            Value::Symbol(symbol) if optional_marker && !symbol.rest => {
+                optional.push((symbol.clone(), Value::Nil));
+            }
+            Value::Symbol(symbol) => takes.push(symbol.clone()),
+            Value::List(pair) if optional_marker => match pair.as_slice() {
+                [Value::Symbol(symbol), default] if !symbol.rest => {
+                    optional.push((symbol.clone(), default.clone()))
+                }
+                _ => return mismatch(environment, "Optional args must be [symbol default] pairs"),
+            },
             _ => return mismatch(environment, "Args list contains a non-symbol value"),
         }
     }
 
-    environment.add_function(name.clone(), Function::new_defun(body, takes));
+    Ok(Defun::new(make_progn(body), takes).with_optional(optional))
+}
 
-    Ok(Value::Nil)
+fn mapcar(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() != 2 {
+        return mismatch(environment, "This function takes a function and a list");
+    }
+
+    let function = args[0].eval(environment)?;
+
+    let elements = match args[1].eval(environment)? {
+        Value::Nil => Vec::new(),
+        Value::List(elements) => elements,
+        _ => return mismatch(environment, "The second argument must be a list"),
+    };
+
+    let mut results: Vec<Value> = Vec::new();
+
+    for element in elements {
+        results.push(invoke(environment, &function, vec![element])?);
+    }
+
+    Ok(Value::List(results))
+}
+
+fn apply(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() != 2 {
+        return mismatch(
+            environment,
+            "This function takes a function and a list of args",
+        );
+    }
+
+    let function = args[0].eval(environment)?;
+
+    let args = match args[1].eval(environment)? {
+        Value::Nil => Vec::new(),
+        Value::List(elements) => elements,
+        _ => return mismatch(environment, "The second argument must be a list"),
+    };
+
+    invoke(environment, &function, args)
+}
+
+fn funcall(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    let function = match args.first() {
+        Some(function) => function.eval(environment)?,
+        None => return mismatch(environment, "This function takes a function and its args"),
+    };
+
+    let mut evaluated: Vec<Value> = Vec::new();
+
+    for arg in args[1..].iter() {
+        evaluated.push(arg.eval(environment)?);
+    }
+
+    invoke(environment, &function, evaluated)
+}
+
+fn sort(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.is_empty() || args.len() > 2 {
+        return mismatch(
+            environment,
+            "This function takes a list and an optional comparator",
+        );
+    }
+
+    let list = args[0].eval(environment)?;
+    let elements = elements(environment, list)?;
+
+    match args.get(1) {
+        Some(comparator) => {
+            let comparator = comparator.eval(environment)?;
+            Ok(Value::List(merge_sort(environment, elements, &comparator)?))
+        }
+        None => {
+            let mut numbers: Vec<Number> = Vec::new();
+
+            for element in elements {
+                match to_number(element) {
+                    Some(number) => numbers.push(number),
+                    None => return mismatch(environment, "Only numbers can be sorted by default"),
+                }
+            }
+
+            numbers.sort_by(|x, y| x.compare(y).unwrap_or(Ordering::Equal));
+
+            Ok(Value::List(numbers.into_iter().map(Value::from).collect()))
+        }
+    }
+}
+
+/// A stable sort calling `comparator` to check whether one element goes before another.
+fn merge_sort(
+    environment: &mut Environment,
+    mut elements: Vec<Value>,
+    comparator: &Value,
+) -> Result<Vec<Value>, EvalError> {
+    if elements.len() < 2 {
+        return Ok(elements);
+    }
+
+    let right = elements.split_off(elements.len() / 2);
+
+    let left = merge_sort(environment, elements, comparator)?;
+    let right = merge_sort(environment, right, comparator)?;
+
+    let mut merged: Vec<Value> = Vec::new();
+
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+
+    while let (Some(x), Some(y)) = (left.peek(), right.peek()) {
+        let args = vec![y.clone(), x.clone()];
+
+        if is_nil(&invoke(environment, comparator, args)?) {
+            merged.push(left.next().unwrap());
+        } else {
+            merged.push(right.next().unwrap());
+        }
+    }
+
+    merged.extend(left);
+    merged.extend(right);
+
+    Ok(merged)
+}
+
+fn reduce(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() != 3 {
+        return mismatch(
+            environment,
+            "This function takes a function, an initial value and a list",
+        );
+    }
+
+    let function = args[0].eval(environment)?;
+    let mut accumulator = args[1].eval(environment)?;
+
+    let list = args[2].eval(environment)?;
+
+    for element in elements(environment, list)? {
+        accumulator = invoke(environment, &function, vec![accumulator, element])?;
+    }
+
+    Ok(accumulator)
+}
+
+fn filter(environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+    if args.len() != 2 {
+        return mismatch(environment, "This function takes a predicate and a list");
+    }
+
+    let predicate = args[0].eval(environment)?;
+
+    let list = args[1].eval(environment)?;
+    let mut selected: Vec<Value> = Vec::new();
+
+    for element in elements(environment, list)? {
+        if !is_nil(&invoke(environment, &predicate, vec![element.clone()])?) {
+            selected.push(element);
+        }
+    }
+
+    Ok(Value::List(selected))
 }
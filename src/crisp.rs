@@ -1,22 +1,67 @@
-use crate::parsers::{parse, ParserError};
+use crate::parsers::{parse, parse_all, ParserError};
 
-use std::collections::HashMap;
-use std::io::Read;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
 
-#[derive(Debug, Clone, Eq, Hash)]
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Symbol {
     pub name: String,
     pub quote: Quote,
     pub rest: bool,
+    /// The package of a qualified function name like `math:square`.
+    pub package: Option<String>,
 }
 
 impl Symbol {
     pub fn new(name: String, quote: Quote, rest: bool) -> Self {
-        Self { name, quote, rest }
+        Self {
+            name,
+            quote,
+            rest,
+            package: None,
+        }
     }
 
+    /// Make a symbol from a name, which may be qualified with a package.
     pub fn from_str(name: &str) -> Self {
-        Self::new(name.to_string(), Quote::None, false)
+        let (package, name) = Self::split_package(name);
+        Self::new(name, Quote::None, false).in_package(package)
+    }
+
+    pub fn in_package(self, package: Option<String>) -> Self {
+        Self { package, ..self }
+    }
+
+    /// Split `package:name` into its parts. Names without a package, or with a colon at either
+    /// end, are left as they are.
+    pub fn split_package(name: &str) -> (Option<String>, String) {
+        match name.split_once(':') {
+            Some((package, unqualified)) if !package.is_empty() && !unqualified.is_empty() => {
+                (Some(package.to_string()), unqualified.to_string())
+            }
+            _ => (None, name.to_string()),
+        }
+    }
+
+    /// The name variables are bound under, which keeps the package so `math:x` and `x` are
+    /// different variables.
+    pub fn qualified_name(&self) -> Cow<'_, str> {
+        match &self.package {
+            Some(_) => Cow::Owned(self.to_string()),
+            None => Cow::Borrowed(&self.name),
+        }
+    }
+
+    pub fn unquoted(&self) -> Self {
+        Self::new(self.name.clone(), Quote::None, false).in_package(self.package.clone())
     }
 
     // Used in `tests`.
@@ -24,93 +69,268 @@ impl Symbol {
     pub fn as_str(&self) -> &str {
         self.name.as_str()
     }
+}
 
-    pub fn to_string(&self) -> String {
-        self.name.to_string()
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.package {
+            Some(package) => write!(f, "{}:{}", package, self.name),
+            None => write!(f, "{}", self.name),
+        }
     }
 }
 
-impl PartialEq for Symbol {
-    fn eq(&self, other: &Self) -> bool {
-        self.name == other.name && self.quote == other.quote && self.rest == other.rest
+/// Marks the rest of an arg list as optional parameters.
+pub const OPTIONAL: &str = "&optional";
+
+pub type Integer = i64;
+pub type Float = f64;
+
+#[cfg(feature = "bignum")]
+pub use num_bigint::BigInt;
+
+/// Bigints are serialized as decimal strings, which any format can hold whatever their size.
+#[cfg(all(feature = "bignum", feature = "serde"))]
+mod decimal {
+    use super::BigInt;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(i: &BigInt, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(i)
     }
-}
 
-pub type Integer = i32;
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BigInt, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
 
-type Builtin = fn(&mut Environment, Vec<Value>) -> EvalResult;
+/// A native function. It's reference counted rather than boxed so functions stay cheap to clone.
+type Builtin = Rc<dyn Fn(&mut Environment, Vec<Value>) -> EvalResult>;
 
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Defun {
     body: Value,
     takes: Vec<Symbol>,
+    /// `&optional` parameters with their default forms, bound after the required ones.
+    optional: Vec<(Symbol, Value)>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    captured: Captured,
 }
 
-#[derive(Clone)]
-pub enum Function {
-    Builtin(Builtin),
-    Defun(Defun),
-}
+impl Defun {
+    pub fn new(body: Value, takes: Vec<Symbol>) -> Self {
+        Self {
+            body,
+            takes,
+            optional: Vec::new(),
+            captured: Captured::default(),
+        }
+    }
 
-impl Function {
-    pub fn new_defun(body: Value, takes: Vec<Symbol>) -> Self {
-        Self::Defun(Defun { body, takes })
+    pub fn with_optional(self, optional: Vec<(Symbol, Value)>) -> Self {
+        Self { optional, ..self }
     }
 
-    pub fn new_builtin(function: Builtin) -> Self {
-        Self::Builtin(function)
+    /// Close over `captured`, so that the body sees those bindings wherever it's called from.
+    pub fn capturing(self, captured: Captured) -> Self {
+        Self { captured, ..self }
     }
 
-    fn eval_defun(
+    /// Bind the passed args to the parameters in the current frame. Args are evaluated unless
+    /// the parameter is quoted or `evaluate` is unset, all of them before any is bound, so that
+    /// they see the caller's bindings rather than the parameters they share names with.
+    fn bind(
         &self,
         environment: &mut Environment,
-        defun: &Defun,
         mut args: Vec<Value>,
-    ) -> EvalResult {
-        for symbol in defun.takes.iter() {
-            if symbol.rest {
-                let value = {
-                    let list = Value::List(args);
-
-                    match symbol.quote {
-                        Quote::Single => list,
-                        _ => list.eval(environment)?,
-                    }
-                };
-
-                environment.current().put(symbol.clone(), value);
+        evaluate: bool,
+    ) -> Result<(), EvalError> {
+        let (required, rest) = match self.takes.split_last() {
+            Some((last, required)) if last.rest => (required, Some(last)),
+            _ => (self.takes.as_slice(), None),
+        };
 
-                break;
+        let argument = |environment: &mut Environment, symbol: &Symbol, arg: Value| {
+            if evaluate && symbol.quote != Quote::Single {
+                arg.eval(environment)
             } else {
-                let value = {
-                    let arg = match args.get(0) {
-                        Some(_) => args.remove(0),
-                        None => {
-                            return Err(EvalError::ArgsMismatch(
-                                "Not enough args passed to the function".into(),
-                            ))
-                        }
-                    };
-
-                    match symbol.quote {
-                        Quote::Single => arg.clone(),
-                        _ => arg.eval(environment)?,
-                    }
-                };
+                Ok(arg)
+            }
+        };
 
-                environment.current().put(symbol.clone(), value);
+        let mut bindings: Vec<(Symbol, Value)> = Vec::new();
+
+        for symbol in required {
+            if args.is_empty() {
+                return Err(EvalError::ArgsMismatch(
+                    "Not enough args passed to the function".into(),
+                ));
             }
+
+            let value = argument(environment, symbol, args.remove(0))?;
+            bindings.push((symbol.clone(), value));
         }
 
-        let result = defun.body.eval(environment);
+        let passed = self.optional.len().min(args.len());
 
-        result
+        for (symbol, _) in self.optional[..passed].iter() {
+            let value = argument(environment, symbol, args.remove(0))?;
+            bindings.push((symbol.clone(), value));
+        }
+
+        if let Some(symbol) = rest {
+            let value = argument(environment, symbol, Value::List(args))?;
+            bindings.push((symbol.clone(), value));
+        }
+
+        for (symbol, value) in bindings {
+            environment.current().put(symbol, value);
+        }
+
+        // Defaults are evaluated in the function's scope, so they may refer to earlier params.
+        for (symbol, default) in self.optional[passed..].iter() {
+            let value = default.eval(environment)?;
+            environment.current().put(symbol.clone(), value);
+        }
+
+        Ok(())
+    }
+
+    /// The values the params are bound to in the current frame, in the order they were passed.
+    fn bound_args(&self, environment: &mut Environment) -> Vec<Value> {
+        let optional = self.optional.iter().map(|(symbol, _)| symbol);
+        let (required, rest) = match self.takes.split_last() {
+            Some((last, required)) if last.rest => (required, Some(last)),
+            _ => (self.takes.as_slice(), None),
+        };
+
+        let mut values: Vec<Value> = required
+            .iter()
+            .chain(optional)
+            .filter_map(|symbol| environment.current().get(symbol))
+            .collect();
+
+        if let Some(Value::List(elements)) =
+            rest.and_then(|symbol| environment.current().get(symbol))
+        {
+            values.extend(elements);
+        }
+
+        values
+    }
+
+    /// Expand a macro: bind the unevaluated argument forms and return what the body evaluates to.
+    pub fn expand(&self, environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+        self.bind(environment, args, false)?;
+        self.body.eval(environment)
+    }
+
+    pub fn call(&self, environment: &mut Environment, args: Vec<Value>) -> EvalResult {
+        self.bind(environment, args, true)?;
+
+        let name = environment.current().caller.clone();
+
+        match environment.functions_table.get(&Symbol::from_str(&name)) {
+            Some(Function::Defun(defun)) if defun == self => (),
+            _ => return self.body.eval_values(environment),
+        }
+
+        // Self-recursive calls in tail position rebind the current frame and loop instead of
+        // growing the stack.
+        loop {
+            match self.eval_tail(environment, &self.body, &name)? {
+                Tail::Return(value) => return Ok(value),
+                Tail::Recur(args) => {
+                    environment.push_to_stack(&name);
+                    let bound = self.bind(environment, args, true);
+                    let frame = environment.pop().unwrap();
+
+                    bound?;
+                    *environment.current() = frame;
+                }
+            }
+        }
+    }
+
+    fn eval_tail(&self, environment: &mut Environment, form: &Value, name: &str) -> TailResult {
+        let (head, args) = match form {
+            Value::Funcall(head, args) => match head.as_ref() {
+                Value::Symbol(head) if head.quote == Quote::None && !head.rest => (head, args),
+                _ => return Ok(Tail::Return(form.eval_values(environment)?)),
+            },
+            _ => return Ok(Tail::Return(form.eval_values(environment)?)),
+        };
+
+        if environment.resolve(head).to_string() == name {
+            return Ok(Tail::Recur(args.clone()));
+        }
+
+        let builtin = matches!(
+            environment.functions_table.get(head),
+            Some(Function::Builtin(_))
+        );
+
+        match head.name.as_str() {
+            "progn" if builtin => self.eval_tail_progn(environment, args, name),
+            "if" if builtin && args.len() >= 2 => {
+                if crate::builtins::is_nil(&args[0].eval(environment)?) {
+                    self.eval_tail_progn(environment, &args[2..], name)
+                } else {
+                    self.eval_tail(environment, &args[1], name)
+                }
+            }
+            _ => Ok(Tail::Return(form.eval_values(environment)?)),
+        }
+    }
+
+    fn eval_tail_progn(
+        &self,
+        environment: &mut Environment,
+        forms: &[Value],
+        name: &str,
+    ) -> TailResult {
+        match forms.split_last() {
+            Some((last, forms)) => {
+                for form in forms {
+                    form.eval(environment)?;
+                }
+
+                self.eval_tail(environment, last, name)
+            }
+            None => Ok(Tail::Return(Value::Nil)),
+        }
+    }
+}
+
+enum Tail {
+    Return(Value),
+    Recur(Vec<Value>),
+}
+
+type TailResult = Result<Tail, EvalError>;
+
+#[derive(Clone)]
+pub enum Function {
+    Builtin(Builtin),
+    Defun(Defun),
+}
+
+impl Function {
+    pub fn new_builtin<F>(function: F) -> Self
+    where
+        F: Fn(&mut Environment, Vec<Value>) -> EvalResult + 'static,
+    {
+        Self::Builtin(Rc::new(function))
     }
 
     pub fn call(&self, environment: &mut Environment, args: Vec<Value>) -> EvalResult {
         match self {
             Self::Builtin(function) => function(environment, args),
-            Self::Defun(defun) => self.eval_defun(environment, defun, args),
+            Self::Defun(defun) => defun.call(environment, args),
         }
     }
 }
@@ -118,52 +338,221 @@ impl Function {
 #[derive(Debug)]
 pub enum EvalError {
     ArgsMismatch(String),
-    SomethingWentWrong, // placeholder.
     VariableIsVoid(String),
-    FunctionDefinitionIsVoid(String),
+    DivisionByZero(String),
+    ArithmeticOverflow(String),
+    /// The name of the function, and the closest defined one if any is close enough.
+    FunctionDefinitionIsVoid(String, Option<String>),
+    StackOverflow(String),
+    Thrown {
+        tag: Value,
+        value: Value,
+    },
+    UserError(String),
+    ConstantReassigned(String),
     FailedToParse(ParserError),
+    FailedToParseFile(String, ParserError),
     FailedToReadFile(String, std::io::Error),
+    FailedToWriteFile(String, std::io::Error),
+    /// Writing to the environment's output, stdout by default, failed.
+    FailedToWriteOutput(std::io::Error),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::ArgsMismatch(reason) => write!(f, "Args mismatch: {}", reason),
+            EvalError::VariableIsVoid(name) => write!(f, "Variable is void: {}", name),
+            EvalError::DivisionByZero(caller) => write!(f, "Division by zero: {}", caller),
+            EvalError::ArithmeticOverflow(caller) => write!(f, "Arithmetic overflow: {}", caller),
+            EvalError::FunctionDefinitionIsVoid(name, None) => {
+                write!(f, "Function definition is void: {}", name)
+            }
+            EvalError::FunctionDefinitionIsVoid(name, Some(suggestion)) => write!(
+                f,
+                "Function definition is void: {}. Did you mean {}?",
+                name, suggestion
+            ),
+            EvalError::StackOverflow(caller) => write!(f, "Stack overflow: {}", caller),
+            EvalError::Thrown { tag, value } => write!(f, "No catch for tag: {}, {}", tag, value),
+            EvalError::UserError(message) => write!(f, "{}", message),
+            EvalError::ConstantReassigned(name) => write!(f, "Constant reassigned: {}", name),
+            EvalError::FailedToParse(error) => write!(f, "Failed to parse: {}", error),
+            EvalError::FailedToParseFile(name, error) => {
+                write!(f, "Failed to parse {}: {}", name, error)
+            }
+            EvalError::FailedToReadFile(name, error) => {
+                write!(f, "Failed to read file {}: {}", name, error)
+            }
+            EvalError::FailedToWriteFile(name, error) => {
+                write!(f, "Failed to write file {}: {}", name, error)
+            }
+            EvalError::FailedToWriteOutput(error) => write!(f, "Failed to write output: {}", error),
+        }
+    }
 }
 
 pub type EvalResult = Result<Value, EvalError>;
 
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Quote {
+    /// `x` evaluates to the value of `x`.
     None,
+    /// `'x` evaluates to the symbol itself.
     Single,
+    /// `,x` evaluates to the value of `x`, which is then evaluated in turn. Inside a quasiquote,
+    /// it's substituted by the value of `x` instead.
     Eval,
 }
 
-#[derive(Debug, Clone, Eq)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Value {
     Nil,
     T,
     Integer(Integer),
+    /// An integer too large for `Integer`. Ones that fit are always kept as `Integer` instead, see
+    /// `Value::big`.
+    #[cfg(feature = "bignum")]
+    #[cfg_attr(feature = "serde", serde(with = "decimal"))]
+    BigInt(BigInt),
+    /// An exact fraction like `1/3`, in lowest terms and with a denominator above 1. Build it with
+    /// `Value::ratio`.
+    Ratio(Integer, Integer),
+    Float(Float),
     String(String),
+    Char(char),
     Symbol(Symbol),
-    Funcall(Symbol, Vec<Value>),
+    Funcall(Box<Value>, Vec<Value>),
+    /// A `[...]` literal evaluates its elements, like `(list ...)`. Quote it to get the elements
+    /// as they are, e.g. `'[(+ 1 2)]` holds the funcall itself. Evaluated lists are only ever
+    /// evaluated once: looking them up, or passing them around, leaves the elements as they are.
     List(Vec<Value>),
+    /// An improper list like `(1 2 . 3)`: elements followed by a tail that isn't a list. It prints
+    /// that way and reads back from either `(1 2 . 3)` or `[1 2 . 3]`, evaluating its elements
+    /// like a `[...]` literal. Build it with `Value::dotted`, which turns a list tail back into a
+    /// proper list.
+    Dotted(Vec<Value>, Box<Value>),
+    Lambda(Box<Defun>),
+    #[cfg_attr(feature = "serde", serde(with = "entries"))]
+    HashMap(HashMap<HashKey, Value>),
+    /// What `values` returns. Function bodies pass them on, but any other use only sees the
+    /// first one, see `Value::eval_values`.
+    Values(Vec<Value>),
+}
+
+/// The values that can be used as hash map keys.
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub enum HashKey {
+    Nil,
+    T,
+    Integer(Integer),
+    String(String),
+    Char(char),
+    Symbol(Symbol),
+}
+
+impl HashKey {
+    /// Returns `None` if the value can't be hashed, like a list or a float.
+    pub fn new(value: Value) -> Option<Self> {
+        match value {
+            Value::Nil => Some(HashKey::Nil),
+            Value::T => Some(HashKey::T),
+            Value::Integer(i) => Some(HashKey::Integer(i)),
+            Value::String(string) => Some(HashKey::String(string)),
+            Value::Char(character) => Some(HashKey::Char(character)),
+            Value::Symbol(symbol) => Some(HashKey::Symbol(symbol)),
+            _ => None,
+        }
+    }
+}
+
+/// Hash maps are serialized as a list of key/value pairs, since most formats only allow string
+/// keys in their maps.
+#[cfg(feature = "serde")]
+mod entries {
+    use super::{HashKey, Value};
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::collections::HashMap;
+
+    pub fn serialize<S: Serializer>(
+        map: &HashMap<HashKey, Value>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            map.iter()
+                .map(|(key, value)| (Value::from(key.clone()), value)),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<HashKey, Value>, D::Error> {
+        Vec::<(Value, Value)>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(key, value)| match HashKey::new(key) {
+                Some(key) => Ok((key, value)),
+                None => Err(D::Error::custom("hash map keys must be hashable")),
+            })
+            .collect()
+    }
+}
+
+/// `t` for true and `nil` for false, the canonical booleans.
+impl From<bool> for Value {
+    fn from(boolean: bool) -> Self {
+        match boolean {
+            true => Value::T,
+            false => Value::Nil,
+        }
+    }
+}
+
+impl From<HashKey> for Value {
+    fn from(key: HashKey) -> Self {
+        match key {
+            HashKey::Nil => Value::Nil,
+            HashKey::T => Value::T,
+            HashKey::Integer(i) => Value::Integer(i),
+            HashKey::String(string) => Value::String(string),
+            HashKey::Char(character) => Value::Char(character),
+            HashKey::Symbol(symbol) => Value::Symbol(symbol),
+        }
+    }
 }
 
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match self {
-            Value::Nil => match other {
-                Value::Nil => true,
+            Value::Nil => matches!(other, Value::Nil),
+            Value::T => matches!(other, Value::T),
+            Value::Integer(i) => match other {
+                Value::Integer(j) => i == j,
                 _ => false,
             },
-            Value::T => match other {
-                Value::T => true,
+            #[cfg(feature = "bignum")]
+            Value::BigInt(i) => match other {
+                Value::BigInt(j) => i == j,
                 _ => false,
             },
-            Value::Integer(i) => match other {
-                Value::Integer(j) => i == j,
+            Value::Ratio(n1, d1) => match other {
+                Value::Ratio(n2, d2) => n1 == n2 && d1 == d2,
+                _ => false,
+            },
+            Value::Float(x) => match other {
+                Value::Float(y) => x == y,
                 _ => false,
             },
             Value::String(s1) => match other {
                 Value::String(s2) => s1 == s2,
                 _ => false,
             },
+            Value::Char(c1) => match other {
+                Value::Char(c2) => c1 == c2,
+                _ => false,
+            },
             Value::Symbol(s1) => match other {
                 Value::Symbol(s2) => s1 == s2,
                 _ => false,
@@ -176,25 +565,317 @@ impl PartialEq for Value {
                 Value::List(v2) => v1 == v2,
                 _ => false,
             },
+            Value::Dotted(v1, tail1) => match other {
+                Value::Dotted(v2, tail2) => v1 == v2 && tail1 == tail2,
+                _ => false,
+            },
+            Value::Lambda(l1) => match other {
+                Value::Lambda(l2) => l1 == l2,
+                _ => false,
+            },
+            Value::HashMap(m1) => match other {
+                Value::HashMap(m2) => m1 == m2,
+                _ => false,
+            },
+            Value::Values(v1) => match other {
+                Value::Values(v2) => v1 == v2,
+                _ => false,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Quote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Quote::None => Ok(()),
+            Quote::Single => write!(f, "'"),
+            Quote::Eval => write!(f, ","),
+        }
+    }
+}
+
+fn write_joined(f: &mut fmt::Formatter, values: &[Value]) -> fmt::Result {
+    for (index, value) in values.iter().enumerate() {
+        if index > 0 {
+            write!(f, " ")?;
+        }
+
+        write!(f, "{}", value)?;
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for Defun {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut takes: Vec<Value> = self.takes.iter().cloned().map(Value::Symbol).collect();
+
+        if !self.optional.is_empty() {
+            let at = takes.len() - self.takes.iter().filter(|symbol| symbol.rest).count();
+
+            let optional = self.optional.iter().map(|(symbol, default)| match default {
+                Value::Nil => Value::Symbol(symbol.clone()),
+                default => Value::List(vec![Value::Symbol(symbol.clone()), default.clone()]),
+            });
+
+            takes.splice(
+                at..at,
+                std::iter::once(Value::Symbol(Symbol::from_str(OPTIONAL))).chain(optional),
+            );
+        }
+
+        write!(f, "(lambda [")?;
+        write_joined(f, &takes)?;
+        write!(f, "]")?;
+
+        // Bodies are always wrapped in a `progn`; print its forms directly.
+        let forms = match &self.body {
+            Value::Funcall(head, forms) if **head == Value::Symbol(Symbol::from_str("progn")) => {
+                forms.clone()
+            }
+            body => vec![body.clone()],
+        };
+
+        for form in forms.iter() {
+            write!(f, " {}", form)?;
+        }
+
+        write!(f, ")")
+    }
+}
+
+/// Prints values back as crisp source.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::T => write!(f, "t"),
+            Value::Integer(i) => write!(f, "{}", i),
+            #[cfg(feature = "bignum")]
+            Value::BigInt(i) => write!(f, "{}", i),
+            Value::Ratio(numerator, denominator) => write!(f, "{}/{}", numerator, denominator),
+            // `Debug` keeps a decimal point, or switches to an exponent like `1e300` for very large
+            // and small numbers. Floats are always finite, so either way it parses as a float.
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::String(string) => {
+                write!(f, "\"")?;
+
+                for character in string.chars() {
+                    match character {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        _ => write!(f, "{}", character)?,
+                    }
+                }
+
+                write!(f, "\"")
+            }
+            Value::Char(character) => match character {
+                '\n' => write!(f, "?\\n"),
+                '\t' => write!(f, "?\\t"),
+                ' ' => write!(f, "?\\s"),
+                '\\' | '(' | ')' | '[' | ']' | '"' | ';' => write!(f, "?\\{}", character),
+                _ => write!(f, "?{}", character),
+            },
+            Value::Symbol(symbol) => {
+                write!(f, "{}{}", symbol.quote, symbol)?;
+
+                if symbol.rest {
+                    write!(f, "...")?;
+                }
+
+                Ok(())
+            }
+            Value::Funcall(head, args) => {
+                write!(f, "({}", head)?;
+
+                for arg in args.iter() {
+                    write!(f, " {}", arg)?;
+                }
+
+                write!(f, ")")
+            }
+            Value::List(elements) => {
+                write!(f, "[")?;
+                write_joined(f, elements)?;
+                write!(f, "]")
+            }
+            Value::Dotted(elements, tail) => {
+                write!(f, "(")?;
+                write_joined(f, elements)?;
+                write!(f, " . {})", tail)
+            }
+            Value::Lambda(lambda) => write!(f, "{}", lambda),
+            // Printed as the call that creates it, with the entries sorted to be deterministic.
+            Value::HashMap(map) => {
+                let mut entries: Vec<String> = map
+                    .iter()
+                    .map(|(key, value)| format!(" {} {}", Value::from(key.clone()), value))
+                    .collect();
+
+                entries.sort();
+
+                write!(f, "(make-hash{})", entries.concat())
+            }
+            // Also printed as the call that creates them.
+            Value::Values(values) => {
+                write!(f, "(values")?;
+
+                for value in values.iter() {
+                    write!(f, " {}", value)?;
+                }
+
+                write!(f, ")")
+            }
         }
     }
 }
 
 impl Value {
+    /// An integer value, demoted to `Integer` if it fits.
+    #[cfg(feature = "bignum")]
+    pub fn big(i: BigInt) -> Self {
+        match Integer::try_from(&i) {
+            Ok(i) => Self::Integer(i),
+            Err(_) => Self::BigInt(i),
+        }
+    }
+
+    /// The fraction in lowest terms, or an integer if it divides evenly. `None` if the denominator
+    /// is zero or the result doesn't fit in `Integer`.
+    pub fn ratio(numerator: i128, denominator: i128) -> Option<Self> {
+        if denominator == 0 {
+            return None;
+        }
+
+        let (mut x, mut y) = (numerator.abs(), denominator.abs());
+
+        while y != 0 {
+            (x, y) = (y, x % y);
+        }
+
+        // Keep the sign on the numerator.
+        let divisor = x * denominator.signum();
+        let numerator = Integer::try_from(numerator / divisor).ok()?;
+
+        match Integer::try_from(denominator / divisor).ok()? {
+            1 => Some(Self::Integer(numerator)),
+            denominator => Some(Self::Ratio(numerator, denominator)),
+        }
+    }
+
+    /// Put `elements` in front of `tail` the way nested conses would, e.g. a list tail makes a
+    /// proper list and no elements leave just the tail.
+    pub fn dotted(mut elements: Vec<Value>, tail: Value) -> Self {
+        if elements.is_empty() {
+            return tail;
+        }
+
+        match tail {
+            Self::Nil => Self::List(elements),
+            Self::List(rest) => {
+                elements.extend(rest);
+                Self::List(elements)
+            }
+            Self::Dotted(rest, tail) => {
+                elements.extend(rest);
+                Self::Dotted(elements, tail)
+            }
+            tail => Self::Dotted(elements, Box::new(tail)),
+        }
+    }
+
+    /// Format the value across several lines, putting each element of a list that holds other
+    /// lists on a line of its own, indented past the opening bracket.
+    pub fn pretty(&self) -> String {
+        let mut output = String::new();
+        self.write_pretty(0, &mut output);
+        output
+    }
+
+    fn write_pretty(&self, indent: usize, output: &mut String) {
+        let (open, elements, tail, close): (_, Vec<&Value>, _, _) = match self {
+            Value::List(elements) => ("[", elements.iter().collect(), None, "]"),
+            Value::Funcall(head, args) => (
+                "(",
+                std::iter::once(&**head).chain(args).collect(),
+                None,
+                ")",
+            ),
+            Value::Dotted(elements, tail) => ("(", elements.iter().collect(), Some(tail), ")"),
+            value => return output.push_str(&value.to_string()),
+        };
+
+        let nested = elements
+            .iter()
+            .copied()
+            .chain(tail.map(|tail| &**tail))
+            .any(|element| match element {
+                Value::List(elements) => !elements.is_empty(),
+                Value::Funcall(..) | Value::Dotted(..) => true,
+                _ => false,
+            });
+
+        if !nested {
+            return output.push_str(&self.to_string());
+        }
+
+        let newline = format!("\n{}", " ".repeat(indent + 1));
+
+        output.push_str(open);
+
+        for (index, element) in elements.iter().enumerate() {
+            if index > 0 {
+                output.push_str(&newline);
+            }
+
+            element.write_pretty(indent + 1, output);
+        }
+
+        if let Some(tail) = tail {
+            output.push_str(&newline);
+            output.push_str(". ");
+            tail.write_pretty(indent + 3, output);
+        }
+
+        output.push_str(close);
+    }
+
+    /// The primary value of multiple values, or the value itself otherwise.
+    pub fn first_value(self) -> Self {
+        match self {
+            Self::Values(values) => values.into_iter().next().unwrap_or(Self::Nil),
+            value => value,
+        }
+    }
+
+    /// Evaluate like `eval`, except that calls returning multiple values keep all of them.
+    pub fn eval_values(&self, environment: &mut Environment) -> EvalResult {
+        match self {
+            Self::Funcall(head, args) => eval_funcall(head, args, environment),
+            value => value.eval(environment),
+        }
+    }
+
     pub fn eval(&self, environment: &mut Environment) -> EvalResult {
         match self {
-            Self::Symbol(symbol) => match symbol.quote {
-                Quote::Single => Ok(self.clone()),
-                _ => match environment.lookup(&symbol) {
-                    Some(value) => match symbol.quote {
-                        Quote::None => Ok(value),
-                        Quote::Eval => value.eval(environment),
-                        _ => Err(EvalError::SomethingWentWrong),
-                    },
-                    None => Err(EvalError::VariableIsVoid(symbol.to_string())),
-                },
-            },
-            Self::Funcall(symbol, args) => environment.call(symbol, args.to_vec()),
+            Self::Symbol(symbol) => {
+                let lookup = |environment: &mut Environment| {
+                    environment
+                        .lookup(symbol)
+                        .ok_or_else(|| EvalError::VariableIsVoid(symbol.unquoted().to_string()))
+                };
+
+                match symbol.quote {
+                    Quote::None => lookup(environment),
+                    Quote::Single => Ok(self.clone()),
+                    Quote::Eval => lookup(environment)?.eval(environment),
+                }
+            }
+            Self::Funcall(head, args) => Ok(eval_funcall(head, args, environment)?.first_value()),
             Self::List(elements) => {
                 let mut evaluated: Vec<Self> = Vec::new();
 
@@ -204,41 +885,186 @@ impl Value {
 
                 Ok(Self::List(evaluated))
             }
+            Self::Dotted(elements, tail) => eval_dotted(elements, tail, environment),
             _ => Ok(self.to_owned()),
         }
     }
 }
 
+/// Call a function, keeping all the values it returns.
+#[inline(never)]
+fn eval_funcall(head: &Value, args: &[Value], environment: &mut Environment) -> EvalResult {
+    match head {
+        Value::Symbol(symbol) => match environment.macroexpand(symbol, args)? {
+            Some(expansion) => expansion.eval_values(environment),
+            None => environment.call(symbol, args.to_vec()),
+        },
+        head => match head.eval(environment)? {
+            Value::Lambda(lambda) => environment.call_lambda(&lambda, args.to_vec()),
+            value => Err(EvalError::FunctionDefinitionIsVoid(
+                format!("{:?}", value),
+                None,
+            )),
+        },
+    }
+}
+
+// Kept out of `Value::eval` so that deeply recursive code doesn't pay for its stack space.
+#[inline(never)]
+fn eval_dotted(elements: &[Value], tail: &Value, environment: &mut Environment) -> EvalResult {
+    let mut evaluated: Vec<Value> = Vec::new();
+
+    for element in elements.iter() {
+        evaluated.push(element.eval(environment)?);
+    }
+
+    Ok(Value::dotted(evaluated, tail.eval(environment)?))
+}
+
+/// Variable bindings, shared between a frame and the lambdas created in it.
+type Scope = Rc<RefCell<HashMap<String, Value>>>;
+
+/// The scopes a lambda closes over, innermost first.
+///
+/// These are strong references, so that lambdas keep working after the frame that made them
+/// returns. The catch is that a lambda stored in a scope it captures, like a local function
+/// bound with `(let 'f (lambda ...))`, forms a reference cycle and is never freed. Such leaks are
+/// bounded by the number of frames that do this, which is accepted for now; breaking the cycle
+/// would take a tracing collector rather than `Weak`, since nothing else owns the scope.
+#[derive(Clone, Default)]
+pub struct Captured(Vec<Scope>);
+
+impl Captured {
+    fn find(&self, symbol: &Symbol) -> Option<&Scope> {
+        self.0
+            .iter()
+            .find(|scope| scope.borrow().contains_key(&*symbol.qualified_name()))
+    }
+}
+
+// Scopes may contain the lambdas capturing them, so don't recurse into them.
+impl fmt::Debug for Captured {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Captured({} scopes)", self.0.len())
+    }
+}
+
+impl PartialEq for Captured {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len()
+            && self
+                .0
+                .iter()
+                .zip(other.0.iter())
+                .all(|(x, y)| Rc::ptr_eq(x, y))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Closure {
     pub caller: String,
-    scope: HashMap<String, Value>,
+    scope: Scope,
+    captured: Captured,
+    builtin: bool,
 }
 
 impl Closure {
     pub fn new(caller: String) -> Self {
         Self {
             caller,
-            scope: HashMap::new(),
+            scope: Scope::default(),
+            captured: Captured::default(),
+            builtin: false,
         }
     }
 
+    /// A frame for calling a lambda, which sees the bindings it closed over.
+    pub fn new_capturing(caller: String, captured: Captured) -> Self {
+        Self {
+            captured,
+            ..Self::new(caller)
+        }
+    }
+
+    /// A frame for a builtin call, which `let` looks past when binding locals.
+    pub fn new_builtin(caller: String) -> Self {
+        Self {
+            builtin: true,
+            ..Self::new(caller)
+        }
+    }
+
+    /// Bind `symbol` in this frame, shadowing any captured binding.
     pub fn put(&mut self, symbol: Symbol, value: Value) {
-        self.scope.insert(symbol.name, value);
+        self.scope
+            .borrow_mut()
+            .insert(symbol.qualified_name().into_owned(), value);
     }
 
-    pub fn get(&self, symbol: &Symbol) -> Option<&Value> {
-        self.scope.get(&symbol.name)
+    /// Change the binding of `symbol`, be it in this frame or a captured scope.
+    pub fn update(&mut self, symbol: Symbol, value: Value) {
+        match self.captured.find(&symbol) {
+            Some(scope) if !self.scope.borrow().contains_key(&*symbol.qualified_name()) => {
+                scope
+                    .borrow_mut()
+                    .insert(symbol.qualified_name().into_owned(), value);
+            }
+            _ => self.put(symbol, value),
+        }
+    }
+
+    pub fn get(&self, symbol: &Symbol) -> Option<Value> {
+        if let Some(value) = self.scope.borrow().get(&*symbol.qualified_name()) {
+            return Some(value.clone());
+        }
+
+        self.captured
+            .find(symbol)
+            .and_then(|scope| scope.borrow().get(&*symbol.qualified_name()).cloned())
     }
 
     pub fn has(&self, symbol: &Symbol) -> bool {
-        self.scope.contains_key(&symbol.name)
+        self.scope.borrow().contains_key(&*symbol.qualified_name())
+            || self.captured.find(symbol).is_some()
+    }
+
+    /// Remove the binding of `symbol`, be it in this frame or a captured scope.
+    pub fn remove(&mut self, symbol: &Symbol) -> bool {
+        if self
+            .scope
+            .borrow_mut()
+            .remove(&*symbol.qualified_name())
+            .is_some()
+        {
+            return true;
+        }
+
+        match self.captured.find(symbol) {
+            Some(scope) => scope
+                .borrow_mut()
+                .remove(&*symbol.qualified_name())
+                .is_some(),
+            None => false,
+        }
+    }
+
+    /// The bindings in this frame, sorted by name.
+    pub fn bindings(&self) -> Vec<(String, Value)> {
+        let mut bindings: Vec<(String, Value)> = self
+            .scope
+            .borrow()
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+
+        bindings.sort_by(|x, y| x.0.cmp(&y.0));
+        bindings
     }
 
     // Used in tests only.
     #[allow(dead_code)]
     pub fn put_str(&mut self, key: &str, value: Value) {
-        let symbol = match parse(&key.to_string()).unwrap() {
+        let symbol = match parse(key).unwrap() {
             Value::Symbol(symbol) => symbol,
             _ => panic!("Not a symbol: {}", key),
         };
@@ -247,9 +1073,53 @@ impl Closure {
     }
 }
 
+/// How many defun and lambda calls may be nested before calls fail instead of exhausting the
+/// native stack. Builtins don't count, so `if` and `+` around a recursive call cost nothing here.
+pub const DEFAULT_MAX_DEPTH: usize = 2048;
+
+/// The native stack to evaluate on, leaving every call up to `DEFAULT_MAX_DEPTH` 128 KiB. A debug
+/// build takes about 26 KiB for a defun wrapped in `if` and `+`, and a few times that when its body
+/// nests more builtins, far more than the 8 MiB of a main thread allows for.
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// The Levenshtein distance between two strings: how many characters have to be inserted,
+/// removed or replaced to turn one into the other.
+fn edit_distance(x: &str, y: &str) -> usize {
+    let y: Vec<char> = y.chars().collect();
+    let mut previous: Vec<usize> = (0..=y.len()).collect();
+
+    for (i, x) in x.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, y) in y.iter().enumerate() {
+            let replaced = previous[j] + if x == *y { 0 } else { 1 };
+            current.push(replaced.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+
+        previous = current;
+    }
+
+    previous[y.len()]
+}
+
 pub struct Environment {
     stack: Vec<Closure>,
     functions_table: HashMap<Symbol, Function>,
+    macros_table: HashMap<Symbol, Defun>,
+    constants: HashSet<String>,
+    documentation: HashMap<String, String>,
+    required: HashSet<PathBuf>,
+    package: Option<String>,
+    command_line_args: Vec<String>,
+    traced: HashSet<Symbol>,
+    backtrace: Vec<String>,
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    trace_output: Box<dyn Write>,
+    /// How many defun and lambda calls are in progress.
+    depth: usize,
+    max_depth: usize,
+    gensym_counter: usize,
 }
 
 impl Environment {
@@ -257,6 +1127,20 @@ impl Environment {
         Self {
             stack: vec![Closure::new("top-level".into())],
             functions_table: HashMap::new(),
+            macros_table: HashMap::new(),
+            constants: HashSet::new(),
+            documentation: HashMap::new(),
+            required: HashSet::new(),
+            package: None,
+            command_line_args: Vec::new(),
+            traced: HashSet::new(),
+            backtrace: Vec::new(),
+            input: Box::new(BufReader::new(io::stdin())),
+            output: Box::new(io::stdout()),
+            trace_output: Box::new(io::stderr()),
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            gensym_counter: 0,
         }
     }
 
@@ -274,9 +1158,15 @@ impl Environment {
         self.stack.last_mut().unwrap()
     }
 
-    pub fn outer(&mut self) -> &mut Closure {
-        let index = self.stack.len() - 2;
-        self.stack.get_mut(index).unwrap()
+    /// The innermost frame below the current one that isn't a builtin's, i.e. the body of
+    /// the defun, lambda or top-level code the current call was made from. There's none when
+    /// only the top level is on the stack.
+    pub fn outer(&mut self) -> Option<&mut Closure> {
+        self.stack
+            .iter_mut()
+            .rev()
+            .skip(1)
+            .find(|frame| !frame.builtin)
     }
 
     pub fn push_to_stack(&mut self, caller: &String) {
@@ -287,67 +1177,477 @@ impl Environment {
         self.stack.pop()
     }
 
+    /// Define a function. Unqualified names go into the current package.
     pub fn add_function(&mut self, key: Symbol, function: Function) {
+        let key = match key.package {
+            Some(_) => key,
+            None => key.in_package(self.package.clone()),
+        };
+
         self.functions_table.insert(key, function);
     }
 
+    /// The error for calling an undefined function, suggesting the closest defined name.
+    pub fn void_function(&self, symbol: &Symbol) -> EvalError {
+        let name = symbol.to_string();
+        let limit = (name.chars().count() / 3).max(1);
+
+        let suggestion = self
+            .functions_table
+            .keys()
+            .map(|key| key.to_string())
+            .map(|key| (edit_distance(&name, &key), key))
+            .filter(|(distance, _)| *distance <= limit)
+            .min()
+            .map(|(_, key)| key);
+
+        EvalError::FunctionDefinitionIsVoid(name, suggestion)
+    }
+
+    /// Remove a function definition, returning whether there was one.
+    pub fn remove_function(&mut self, symbol: &Symbol) -> bool {
+        let key = self.resolve(symbol);
+        self.traced.remove(&key);
+        self.functions_table.remove(&key).is_some()
+    }
+
+    /// Switch the package new functions are defined in, `None` being the default one.
+    pub fn set_package(&mut self, package: Option<String>) {
+        self.package = package;
+    }
+
+    /// The key of the function `symbol` refers to. Unqualified names are looked up in the
+    /// current package first, then in the default one, where the builtins are.
+    pub fn resolve(&self, symbol: &Symbol) -> Symbol {
+        if symbol.package.is_none() && self.package.is_some() {
+            let qualified = symbol.clone().in_package(self.package.clone());
+
+            if self.functions_table.contains_key(&qualified) {
+                return qualified;
+            }
+        }
+
+        symbol.clone()
+    }
+
+    pub fn add_macro(&mut self, key: Symbol, transformer: Defun) {
+        self.macros_table.insert(key, transformer);
+    }
+
+    /// Expand a call to a macro, or return `None` if `symbol` doesn't name one.
+    pub fn macroexpand(
+        &mut self,
+        symbol: &Symbol,
+        args: &[Value],
+    ) -> Result<Option<Value>, EvalError> {
+        let transformer = match self.macros_table.get(symbol).cloned() {
+            Some(transformer) => transformer,
+            None => return Ok(None),
+        };
+
+        self.check_depth(&symbol.name)?;
+        self.push_to_stack(&symbol.name);
+        self.depth += 1;
+
+        let expansion = transformer.expand(self, args.to_vec());
+
+        self.depth -= 1;
+        self.pop();
+
+        expansion.map(Some)
+    }
+
+    /// Expose a native function to crisp code under `name`, replacing any existing definition.
+    ///
+    /// Like the builtins, `function` receives its args unevaluated and calls `Value::eval` on
+    /// them itself. It may capture state from the host:
+    ///
+    /// ```ignore
+    /// let greeting = String::from("Hello");
+    ///
+    /// environment.register("greet", move |environment, args| {
+    ///     let name = args[0].eval(environment)?;
+    ///     Ok(Value::String(format!("{}, {}!", greeting, name)))
+    /// });
+    /// ```
+    pub fn register<F>(&mut self, name: &str, function: F)
+    where
+        F: Fn(&mut Environment, Vec<Value>) -> EvalResult + 'static,
+    {
+        self.add_function(Symbol::from_str(name), Function::new_builtin(function));
+    }
+
+    pub fn function(&self, symbol: &Symbol) -> Option<Function> {
+        self.functions_table.get(&self.resolve(symbol)).cloned()
+    }
+
+    /// The names of all defined functions, sorted.
+    pub fn function_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .functions_table
+            .keys()
+            .map(|symbol| symbol.to_string())
+            .collect();
+
+        names.sort_unstable();
+        names
+    }
+
     pub fn lookup(&self, symbol: &Symbol) -> Option<Value> {
         for frame in self.stack.iter().rev() {
             if let Some(value) = frame.get(symbol) {
-                return Some(value.clone());
+                return Some(value);
             }
         }
 
         None
     }
 
-    pub fn find_closure(&mut self, symbol: &Symbol) -> Option<&mut Closure> {
-        for frame in self.stack.iter_mut().rev() {
-            if frame.has(symbol) {
-                return Some(frame);
+    /// The scopes of the frames above top level, for a lambda created now to close over.
+    pub fn capture(&self) -> Captured {
+        let mut scopes: Vec<Scope> = Vec::new();
+
+        for frame in self.stack[1..].iter().rev().filter(|frame| !frame.builtin) {
+            for scope in std::iter::once(&frame.scope).chain(frame.captured.0.iter()) {
+                if !scopes.iter().any(|captured| Rc::ptr_eq(captured, scope)) {
+                    scopes.push(scope.clone());
+                }
             }
         }
 
-        None
+        Captured(scopes)
+    }
+
+    /// Update the innermost binding of `symbol`, or bind it at top level if there's none.
+    /// Top-level constants can't be reassigned this way.
+    pub fn assign(&mut self, symbol: Symbol, value: Value) -> Result<(), EvalError> {
+        let frame = self
+            .stack
+            .iter()
+            .rposition(|frame| frame.has(&symbol))
+            .unwrap_or(0);
+
+        if frame == 0 && self.constants.contains(&*symbol.qualified_name()) {
+            return Err(EvalError::ConstantReassigned(symbol.to_string()));
+        }
+
+        self.stack[frame].update(symbol, value);
+
+        Ok(())
+    }
+
+    /// Remove the innermost binding of `symbol`, returning whether there was one. Top-level
+    /// constants can't be removed.
+    pub fn unbind(&mut self, symbol: &Symbol) -> Result<bool, EvalError> {
+        let frame = match self.stack.iter().rposition(|frame| frame.has(symbol)) {
+            Some(frame) => frame,
+            None => return Ok(false),
+        };
+
+        if frame == 0 && self.constants.contains(&*symbol.qualified_name()) {
+            return Err(EvalError::ConstantReassigned(symbol.to_string()));
+        }
+
+        Ok(self.stack[frame].remove(symbol))
+    }
+
+    pub fn is_constant(&self, symbol: &Symbol) -> bool {
+        self.constants.contains(&*symbol.qualified_name())
+    }
+
+    /// Bind `symbol` at top level, marking it constant if `constant` is set.
+    pub fn define(&mut self, symbol: Symbol, value: Value, constant: bool) {
+        if constant {
+            self.constants.insert(symbol.qualified_name().into_owned());
+        }
+
+        self.top_level().put(symbol, value);
+    }
+
+    pub fn set_documentation(&mut self, symbol: &Symbol, documentation: String) {
+        self.documentation
+            .insert(symbol.qualified_name().into_owned(), documentation);
+    }
+
+    pub fn documentation(&self, symbol: &Symbol) -> Option<&String> {
+        self.documentation.get(&*symbol.qualified_name())
+    }
+
+    /// Make a symbol that isn't bound to a variable or a function yet.
+    pub fn gensym(&mut self) -> Symbol {
+        loop {
+            self.gensym_counter += 1;
+
+            let symbol = Symbol::from_str(&format!("g{}", self.gensym_counter));
+
+            if self.lookup(&symbol).is_none() && !self.functions_table.contains_key(&symbol) {
+                return symbol;
+            }
+        }
+    }
+
+    // Used in `tests`.
+    #[allow(dead_code)]
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    fn check_depth(&self, caller: &str) -> Result<(), EvalError> {
+        if self.depth >= self.max_depth {
+            Err(EvalError::StackOverflow(caller.into()))
+        } else {
+            Ok(())
+        }
     }
 
     pub fn call(&mut self, symbol: &Symbol, args: Vec<Value>) -> EvalResult {
-        self.push_to_stack(&symbol.name);
+        let key = self.resolve(symbol);
+        let function = self.functions_table.get(&key).cloned();
+        let builtin = matches!(function, Some(Function::Builtin(_)));
+
+        if builtin {
+            self.stack.push(Closure::new_builtin(key.to_string()));
+        } else {
+            self.check_depth(&symbol.name)?;
+            self.stack.push(Closure::new(key.to_string()));
+            self.depth += 1;
+        }
 
-        let result = match self.functions_table.get(symbol).cloned() {
+        // Functions from a package see the other functions in it by their unqualified names.
+        let previous_package = match (&function, &key.package) {
+            (Some(_), Some(package)) => Some(self.package.replace(package.clone())),
+            _ => None,
+        };
+
+        let result = match function {
+            Some(Function::Defun(defun)) if self.traced.contains(&key) => {
+                self.call_traced(&key, &defun, args)
+            }
             Some(function) => function.call(self, args),
-            None => Err(EvalError::FunctionDefinitionIsVoid(symbol.to_string())),
+            None => match self.lookup(symbol) {
+                Some(Value::Lambda(lambda)) => {
+                    self.current().captured = lambda.captured.clone();
+                    lambda.call(self, args)
+                }
+                _ => Err(self.void_function(symbol)),
+            },
         };
 
+        if let Some(package) = previous_package {
+            self.package = package;
+        }
+
+        if result.is_err() {
+            self.record_backtrace();
+        }
+
+        if !builtin {
+            self.depth -= 1;
+        }
+
         self.pop();
 
         result
     }
 
-    pub fn eval(&mut self, buffer: &String) -> EvalResult {
+    /// Call a traced defun, printing its args on entry and its value on exit. Tail calls aren't
+    /// optimised here so every recursive call shows up.
+    fn call_traced(&mut self, key: &Symbol, defun: &Defun, args: Vec<Value>) -> EvalResult {
+        // Not counting the top level and the frame just pushed for this call.
+        let indent = " ".repeat(self.stack.len() - 2);
+
+        defun.bind(self, args, true)?;
+
+        let args: Vec<String> = defun
+            .bound_args(self)
+            .iter()
+            .map(|arg| format!(" {}", arg))
+            .collect();
+
+        let _ = writeln!(self.trace_output, "{}({}{})", indent, key, args.concat());
+
+        let result = defun.body.eval_values(self);
+
+        let _ = match &result {
+            Ok(value) => writeln!(self.trace_output, "{}{} returned {}", indent, key, value),
+            Err(error) => writeln!(self.trace_output, "{}{} failed: {}", indent, key, error),
+        };
+
+        result
+    }
+
+    /// Print calls to the defun `symbol` names from now on.
+    pub fn trace(&mut self, symbol: &Symbol) {
+        let key = self.resolve(symbol);
+        self.traced.insert(key);
+    }
+
+    /// Stop tracing the function `symbol` names. Returns whether it was traced.
+    pub fn untrace(&mut self, symbol: &Symbol) -> bool {
+        let key = self.resolve(symbol);
+        self.traced.remove(&key)
+    }
+
+    pub fn call_lambda(&mut self, lambda: &Defun, args: Vec<Value>) -> EvalResult {
+        self.check_depth("lambda")?;
+
+        self.stack.push(Closure::new_capturing(
+            "lambda".into(),
+            lambda.captured.clone(),
+        ));
+        self.depth += 1;
+
+        let result = lambda.call(self, args);
+
+        if result.is_err() {
+            self.record_backtrace();
+        }
+
+        self.depth -= 1;
+        self.pop();
+        result
+    }
+
+    /// Remember the callers on the stack where an error happened, unless it was already recorded
+    /// further down.
+    fn record_backtrace(&mut self) {
+        if self.backtrace.is_empty() {
+            self.backtrace = self.stack[1..]
+                .iter()
+                .rev()
+                .map(|frame| frame.caller.clone())
+                .collect();
+        }
+    }
+
+    /// The callers on the stack when the last unhandled error happened, innermost first. Taking
+    /// it clears it for the next error, as does starting another evaluation with `eval` and
+    /// friends.
+    pub fn take_backtrace(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.backtrace)
+    }
+
+    /// Forget the backtrace of an error that got handled.
+    pub fn clear_backtrace(&mut self) {
+        self.backtrace.clear();
+    }
+
+    pub fn eval(&mut self, buffer: &str) -> EvalResult {
+        self.clear_backtrace();
         parse(buffer).map_err(EvalError::FailedToParse)?.eval(self)
     }
 
+    /// Evaluate every top-level form in the buffer in turn, returning the value of each.
+    pub fn eval_all(&mut self, buffer: &str) -> Result<Vec<Value>, EvalError> {
+        self.clear_backtrace();
+
+        let forms = parse_all(buffer).map_err(EvalError::FailedToParse)?;
+        let mut values: Vec<Value> = Vec::new();
+
+        for form in forms {
+            values.push(form.eval(self)?);
+        }
+
+        Ok(values)
+    }
+
+    /// The arguments passed to the scripts after `--`.
+    pub fn command_line_args(&self) -> &[String] {
+        &self.command_line_args
+    }
+
+    pub fn set_command_line_args(&mut self, args: Vec<String>) {
+        self.command_line_args = args;
+    }
+
+    /// Replace the source `read_line` and `eval_stdin` read from, stdin by default.
+    // Used in `tests`.
+    #[allow(dead_code)]
+    pub fn set_input(&mut self, input: Box<dyn BufRead>) {
+        self.input = input;
+    }
+
+    /// Replace where the printing builtins write to, stdout by default.
+    // Used in `tests`.
+    #[allow(dead_code)]
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    /// Where the printing builtins write to.
+    pub fn output(&mut self) -> &mut dyn Write {
+        self.output.as_mut()
+    }
+
+    /// Replace where `trace` prints calls to, stderr by default.
+    // Used in `tests`.
+    #[allow(dead_code)]
+    pub fn set_trace_output(&mut self, output: Box<dyn Write>) {
+        self.trace_output = output;
+    }
+
+    /// Read a line without its terminator, or `None` at the end of input.
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        crate::repl::read_line(self.input.as_mut())
+    }
+
     pub fn eval_stdin(&mut self) -> EvalResult {
+        self.clear_backtrace();
+
         let mut buffer = String::new();
 
-        if let Err(err) = std::io::stdin().read_to_string(&mut buffer) {
+        if let Err(err) = self.input.read_to_string(&mut buffer) {
             return Err(EvalError::FailedToReadFile("stdin".into(), err));
         }
 
-        self.eval(&format!("(progn {})", buffer))
+        self.eval_source("stdin".into(), &buffer)
     }
 
     pub fn eval_file(&mut self, name: String) -> EvalResult {
+        self.clear_backtrace();
+
         match std::fs::read_to_string(&name) {
-            Ok(buffer) => self.eval(&format!("(progn {})", buffer)),
+            Ok(buffer) => self.eval_source(name, &buffer),
             Err(err) => Err(EvalError::FailedToReadFile(name, err)),
         }
     }
 
+    /// Evaluate a file unless it was required before, returning whether it was evaluated.
+    pub fn require(&mut self, name: String) -> Result<bool, EvalError> {
+        let path = match std::fs::canonicalize(&name) {
+            Ok(path) => path,
+            Err(err) => return Err(EvalError::FailedToReadFile(name, err)),
+        };
+
+        // Mark it early, so that files requiring each other don't loop forever.
+        if !self.required.insert(path.clone()) {
+            return Ok(false);
+        }
+
+        match self.eval_file(name) {
+            Ok(_) => Ok(true),
+            Err(err) => {
+                self.required.remove(&path);
+                Err(err)
+            }
+        }
+    }
+
+    fn eval_source(&mut self, name: String, buffer: &str) -> EvalResult {
+        let forms = parse_all(buffer).map_err(|err| EvalError::FailedToParseFile(name, err))?;
+
+        let mut last = Value::Nil;
+
+        for form in forms {
+            last = form.eval(self)?;
+        }
+
+        Ok(last)
+    }
+
     // Used in `tests`.
     #[allow(dead_code)]
     pub fn eval_str(&mut self, buffer: &str) -> EvalResult {
-        self.eval(&buffer.into())
+        self.eval(buffer)
     }
 }
@@ -6,32 +6,186 @@ mod repl;
 #[cfg(test)]
 mod tests;
 
-use crate::crisp::{Environment, EvalError};
+use crate::crisp::{Environment, EvalError, STACK_SIZE};
+use crate::parsers::parse_all;
+
+use std::fmt;
+use std::io::Write;
 
 #[derive(Debug)]
 enum RuntimeError {
     IO(std::io::Error),
-    Eval(EvalError),
+    /// An error along with the backtrace of where it happened. Boxed to keep results small.
+    Eval(Box<EvalError>, Vec<String>),
+    Usage(String),
 }
 
-fn main() -> Result<(), RuntimeError> {
-    let args: Vec<String> = std::env::args().skip(1).collect();
+impl From<EvalError> for RuntimeError {
+    fn from(error: EvalError) -> Self {
+        RuntimeError::Eval(Box::new(error), Vec::new())
+    }
+}
 
-    if args.is_empty() {
-        return repl::mainloop().map_err(RuntimeError::IO);
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::IO(error) => write!(f, "IO error: {}", error),
+            RuntimeError::Eval(error, backtrace) => {
+                write!(f, "{}", repl::with_backtrace(error.to_string(), backtrace))
+            }
+            RuntimeError::Usage(reason) => write!(f, "Usage error: {}", reason),
+        }
     }
+}
 
-    let mut environment = Environment::new_configured();
+fn main() {
+    // Evaluate on a thread of our own, since the main thread's stack is too small for deep
+    // recursion.
+    let interpreter = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(|| {
+            if let Err(error) = run() {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        })
+        .expect("Failed to start the interpreter thread");
+
+    if interpreter.join().is_err() {
+        std::process::exit(101);
+    }
+}
+
+const USAGE: &str = "\
+Usage: crisp [OPTIONS] [FILE]... [-- ARG...]
+
+Evaluate crisp files in order, or start a REPL if none are given. `-` reads from stdin.
+Arguments after `--` are left to the scripts, which get them with `(command-line-args)`.
+
+Options:
+  -e, --eval <EXPR>   evaluate an expression and print its value
+      --check <FILE>  parse a file without evaluating it, reporting the first syntax error
+  -h, --help          print this message
+  -V, --version       print the version";
+
+#[derive(Debug, PartialEq)]
+enum Task {
+    Eval(String),
+    File(String),
+    Check(String),
+    Stdin,
+    Help,
+    Version,
+}
+
+fn parse_args(args: Vec<String>) -> Result<Vec<Task>, RuntimeError> {
+    let mut tasks: Vec<Task> = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        tasks.push(match arg.as_str() {
+            "-h" | "--help" => return Ok(vec![Task::Help]),
+            "-V" | "--version" => return Ok(vec![Task::Version]),
+            "-e" | "--eval" => match args.next() {
+                Some(expression) => Task::Eval(expression),
+                None => return Err(RuntimeError::Usage(format!("{} takes an expression", arg))),
+            },
+            "--check" => match args.next() {
+                Some(file) => Task::Check(file),
+                None => return Err(RuntimeError::Usage(format!("{} takes a file", arg))),
+            },
+            "-" => Task::Stdin,
+            flag if flag.starts_with('-') => {
+                return Err(RuntimeError::Usage(format!(
+                    "Unknown flag: {}. Try --help",
+                    flag
+                )))
+            }
+            _ => Task::File(arg),
+        });
+    }
+
+    Ok(tasks)
+}
 
-    for file in args {
-        let result = if file.as_str() == "-" {
-            environment.eval_stdin()
-        } else {
-            environment.eval_file(file)
-        };
+/// Split off the arguments after `--`, which are meant for the scripts rather than crisp itself.
+fn split_script_args(mut args: Vec<String>) -> (Vec<String>, Vec<String>) {
+    match args.iter().position(|arg| arg == "--") {
+        Some(index) => {
+            let script_args = args.split_off(index + 1);
+            args.pop();
+            (args, script_args)
+        }
+        None => (args, Vec::new()),
+    }
+}
+
+/// Parse a file without evaluating any of it, so that checking it has no side effects.
+fn check_file(name: String) -> Result<(), EvalError> {
+    let buffer = match std::fs::read_to_string(&name) {
+        Ok(buffer) => buffer,
+        Err(err) => return Err(EvalError::FailedToReadFile(name, err)),
+    };
+
+    match parse_all(&buffer) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(EvalError::FailedToParseFile(name, err)),
+    }
+}
+
+/// Run the tasks in order, writing what `-e` expressions evaluate to into `output`. Errors come
+/// with the backtrace of where they happened.
+fn run_tasks(
+    environment: &mut Environment,
+    tasks: Vec<Task>,
+    output: &mut dyn Write,
+) -> Result<(), RuntimeError> {
+    for task in tasks {
+        run_task(environment, task, output).map_err(|error| match error {
+            RuntimeError::Eval(error, _) => RuntimeError::Eval(error, environment.take_backtrace()),
+            error => error,
+        })?;
+    }
+
+    Ok(())
+}
 
-        result.map_err(RuntimeError::Eval)?;
+fn run_task(
+    environment: &mut Environment,
+    task: Task,
+    output: &mut dyn Write,
+) -> Result<(), RuntimeError> {
+    match task {
+        Task::Eval(expression) => {
+            let value = environment.eval(&expression)?;
+            writeln!(output, "{}", value).map_err(RuntimeError::IO)?;
+        }
+        Task::File(file) => {
+            environment.eval_file(file)?;
+        }
+        Task::Check(file) => check_file(file)?,
+        Task::Stdin => {
+            environment.eval_stdin()?;
+        }
+        Task::Help => writeln!(output, "{}", USAGE).map_err(RuntimeError::IO)?,
+        Task::Version => {
+            writeln!(output, "crisp {}", env!("CARGO_PKG_VERSION")).map_err(RuntimeError::IO)?
+        }
     }
 
     Ok(())
 }
+
+fn run() -> Result<(), RuntimeError> {
+    let (args, script_args) = split_script_args(std::env::args().skip(1).collect());
+    let tasks = parse_args(args)?;
+
+    let mut environment = Environment::new_configured();
+    environment.set_command_line_args(script_args);
+
+    if tasks.is_empty() {
+        return repl::mainloop(environment).map_err(RuntimeError::IO);
+    }
+
+    run_tasks(&mut environment, tasks, &mut std::io::stdout())
+}
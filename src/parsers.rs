@@ -1,11 +1,12 @@
-use crate::crisp::{Integer, Quote, Symbol, Value};
+use crate::crisp::{Float, Integer, Quote, Symbol, Value};
 
 use regex::Regex;
 
 use std::collections::HashMap;
+use std::fmt;
 
-#[derive(Debug)]
-pub enum ParserError {
+#[derive(Debug, PartialEq)]
+pub enum ParserErrorKind {
     MalformedInput(String),
     IntegerOverflow,
     InvalidEscapeSequence(char),
@@ -13,14 +14,75 @@ pub enum ParserError {
     EmptyFuncall,
     InvalidFuncall,
     NoMatchingParser,
+    MisplacedDot,
+}
+
+impl fmt::Display for ParserErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParserErrorKind::MalformedInput(reason) => write!(f, "Malformed input: {}", reason),
+            ParserErrorKind::IntegerOverflow => write!(f, "Integer literal is too large"),
+            ParserErrorKind::InvalidEscapeSequence(character) => {
+                write!(f, "Invalid escape sequence: \\{}", character)
+            }
+            ParserErrorKind::UnmatchedParentheses => write!(f, "Unmatched parentheses"),
+            ParserErrorKind::EmptyFuncall => write!(f, "Empty function call"),
+            ParserErrorKind::InvalidFuncall => write!(f, "Invalid function call"),
+            ParserErrorKind::NoMatchingParser => write!(f, "No matching parser"),
+            ParserErrorKind::MisplacedDot => write!(f, "Misplaced dot"),
+        }
+    }
+}
+
+/// A parser error along with where it occurred.
+///
+/// `offset` is a byte offset into the buffer being parsed. `line` and `column` are 1-based and
+/// filled in by the top-level `parse`.
+#[derive(Debug)]
+pub struct ParserError {
+    pub kind: ParserErrorKind,
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl ParserError {
+    pub fn new(kind: ParserErrorKind, offset: usize) -> Self {
+        Self {
+            kind,
+            offset,
+            line: 1,
+            column: offset + 1,
+        }
+    }
+
+    fn shifted(mut self, by: usize) -> Self {
+        self.offset += by;
+        self
+    }
+
+    fn locate(mut self, buffer: &str) -> Self {
+        let before = buffer.get(..self.offset).unwrap_or(buffer);
+
+        self.line = before.matches('\n').count() + 1;
+        self.column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+
+        self
+    }
+}
+
+impl fmt::Display for ParserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}:{}", self.kind, self.line, self.column)
+    }
 }
 
 pub type ParserCheckResult = Result<(), ParserError>;
 pub type ParserResult = Result<Value, ParserError>;
 
 pub trait Parser {
-    fn has_next(&self, buffer: &String) -> ParserCheckResult;
-    fn parse(&self, buffer: &String) -> ParserResult;
+    fn has_next(&self, buffer: &str) -> ParserCheckResult;
+    fn parse(&self, buffer: &str) -> ParserResult;
 }
 
 struct IntegerParser {
@@ -30,23 +92,39 @@ struct IntegerParser {
 impl IntegerParser {
     fn new() -> Self {
         Self {
-            regex: Regex::new(r"(?P<sign>^\+|^-|^)(?P<number>[0-9]+)$").unwrap(),
+            regex: Regex::new(
+                r"^(?P<sign>[+-]?)(0x(?P<hex>[0-9a-fA-F]+)|0o(?P<octal>[0-7]+)|0b(?P<binary>[01]+)|(?P<decimal>[0-9]+))$",
+            )
+            .unwrap(),
         }
     }
 }
 
+/// Parse an integer literal too large for `Integer`.
+#[cfg(feature = "bignum")]
+fn big_integer(sign: Integer, radix: u32, digits: &str) -> ParserResult {
+    let number = crate::crisp::BigInt::parse_bytes(digits.as_bytes(), radix).unwrap();
+    Ok(Value::big(number * sign))
+}
+
+#[cfg(not(feature = "bignum"))]
+fn big_integer(_sign: Integer, _radix: u32, _digits: &str) -> ParserResult {
+    Err(ParserError::new(ParserErrorKind::IntegerOverflow, 0))
+}
+
 impl Parser for IntegerParser {
-    fn has_next(&self, buffer: &String) -> ParserCheckResult {
+    fn has_next(&self, buffer: &str) -> ParserCheckResult {
         if self.regex.is_match(buffer) {
             Ok(())
         } else {
-            Err(ParserError::MalformedInput(
-                "Regex doesn't match".to_string(),
+            Err(ParserError::new(
+                ParserErrorKind::MalformedInput("Regex doesn't match".to_string()),
+                0,
             ))
         }
     }
 
-    fn parse(&self, buffer: &String) -> ParserResult {
+    fn parse(&self, buffer: &str) -> ParserResult {
         let captures = self.regex.captures(buffer).unwrap();
 
         let sign: Integer = match captures.name("sign").unwrap().as_str() {
@@ -54,21 +132,160 @@ impl Parser for IntegerParser {
             _ => 1,
         };
 
+        let (radix, digits) = [("hex", 16), ("octal", 8), ("binary", 2), ("decimal", 10)]
+            .iter()
+            .find_map(|(name, radix)| captures.name(name).map(|digits| (*radix, digits.as_str())))
+            .unwrap();
+
         let mut number: Integer = 0;
 
-        for character in captures.name("number").unwrap().as_str().chars() {
-            let digit = (character as u8 - b'0').into();
+        // Accumulate with the sign applied so the most negative integer still fits.
+        for character in digits.chars() {
+            let digit = character.to_digit(radix).unwrap() as Integer * sign;
 
-            match number.checked_mul(10) {
-                Some(result) => match result.checked_add(digit) {
-                    Some(result) => number = result,
-                    None => return Err(ParserError::IntegerOverflow),
-                },
-                None => return Err(ParserError::IntegerOverflow),
+            match number
+                .checked_mul(radix as Integer)
+                .and_then(|result| result.checked_add(digit))
+            {
+                Some(result) => number = result,
+                None => return big_integer(sign, radix, digits),
             }
         }
 
-        Ok(Value::Integer(number * sign))
+        Ok(Value::Integer(number))
+    }
+}
+
+struct FloatParser {
+    regex: Regex,
+}
+
+impl FloatParser {
+    fn new() -> Self {
+        let re = r"^[+-]?[0-9]+(\.[0-9]+([eE][+-]?[0-9]+)?|[eE][+-]?[0-9]+)$";
+
+        Self {
+            regex: Regex::new(re).unwrap(),
+        }
+    }
+}
+
+impl Parser for FloatParser {
+    fn has_next(&self, buffer: &str) -> ParserCheckResult {
+        if self.regex.is_match(buffer) {
+            Ok(())
+        } else {
+            Err(ParserError::new(
+                ParserErrorKind::MalformedInput("Regex doesn't match".to_string()),
+                0,
+            ))
+        }
+    }
+
+    fn parse(&self, buffer: &str) -> ParserResult {
+        match buffer.parse::<Float>() {
+            Ok(float) if float.is_finite() => Ok(Value::Float(float)),
+            _ => Err(ParserError::new(
+                ParserErrorKind::MalformedInput("Float literal out of range".to_string()),
+                0,
+            )),
+        }
+    }
+}
+
+/// Exact fractions like `1/3` or `-2/4`, which is read as `-1/2`.
+struct RatioParser {
+    regex: Regex,
+}
+
+impl RatioParser {
+    fn new() -> Self {
+        Self {
+            regex: Regex::new(r"^(?P<numerator>[+-]?[0-9]+)/(?P<denominator>[0-9]+)$").unwrap(),
+        }
+    }
+}
+
+impl Parser for RatioParser {
+    fn has_next(&self, buffer: &str) -> ParserCheckResult {
+        if self.regex.is_match(buffer) {
+            Ok(())
+        } else {
+            Err(ParserError::new(
+                ParserErrorKind::MalformedInput("Regex doesn't match".to_string()),
+                0,
+            ))
+        }
+    }
+
+    fn parse(&self, buffer: &str) -> ParserResult {
+        let captures = self.regex.captures(buffer).unwrap();
+        let overflow = || ParserError::new(ParserErrorKind::IntegerOverflow, 0);
+
+        let numerator: i128 = captures["numerator"].parse().map_err(|_| overflow())?;
+        let denominator: i128 = captures["denominator"].parse().map_err(|_| overflow())?;
+
+        match Value::ratio(numerator, denominator) {
+            Some(value) => Ok(value),
+            None if denominator == 0 => Err(ParserError::new(
+                ParserErrorKind::MalformedInput("Zero denominator".to_string()),
+                0,
+            )),
+            None => Err(overflow()),
+        }
+    }
+}
+
+/// Character literals: `?a`, or `?\n`, `?\t`, `?\s` (a space) and `?\\` for the special ones.
+/// Brackets, quotes and `;` can be escaped too.
+struct CharParser {
+    regex: Regex,
+}
+
+impl CharParser {
+    fn new() -> Self {
+        Self {
+            regex: Regex::new(r"^\?(\\(?P<escape>.)|(?P<character>[^\\\s]))$").unwrap(),
+        }
+    }
+}
+
+impl Parser for CharParser {
+    fn has_next(&self, buffer: &str) -> ParserCheckResult {
+        if self.regex.is_match(buffer) {
+            Ok(())
+        } else {
+            Err(ParserError::new(
+                ParserErrorKind::MalformedInput("Not a character literal".into()),
+                0,
+            ))
+        }
+    }
+
+    fn parse(&self, buffer: &str) -> ParserResult {
+        let captures = self.regex.captures(buffer).unwrap();
+
+        if let Some(character) = captures.name("character") {
+            return Ok(Value::Char(character.as_str().chars().next().unwrap()));
+        }
+
+        match captures
+            .name("escape")
+            .unwrap()
+            .as_str()
+            .chars()
+            .next()
+            .unwrap()
+        {
+            'n' => Ok(Value::Char('\n')),
+            't' => Ok(Value::Char('\t')),
+            's' => Ok(Value::Char(' ')),
+            character if "\\()[]\";".contains(character) => Ok(Value::Char(character)),
+            character => Err(ParserError::new(
+                ParserErrorKind::InvalidEscapeSequence(character),
+                2,
+            )),
+        }
     }
 }
 
@@ -87,121 +304,397 @@ impl SymbolParser {
 }
 
 impl Parser for SymbolParser {
-    fn has_next(&self, buffer: &String) -> ParserCheckResult {
+    fn has_next(&self, buffer: &str) -> ParserCheckResult {
         if self.regex.is_match(buffer) {
             Ok(())
         } else {
-            Err(ParserError::MalformedInput(
-                "Illegal characters in symbol name".to_string(),
+            Err(ParserError::new(
+                ParserErrorKind::MalformedInput("Illegal characters in symbol name".to_string()),
+                0,
             ))
         }
     }
 
-    fn parse(&self, buffer: &String) -> ParserResult {
+    fn parse(&self, buffer: &str) -> ParserResult {
         let captures = self.regex.captures(buffer).unwrap();
-
-        Ok(Value::Symbol(Symbol::new(
-            captures.name("symbol").unwrap().as_str().to_owned(),
-            match captures.name("q") {
-                Some(capture) => match capture.as_str() {
-                    "'" => Quote::Single,
-                    "," => Quote::Eval,
-                    _ => return Err(ParserError::NoMatchingParser),
+        let (package, name) = Symbol::split_package(captures.name("symbol").unwrap().as_str());
+
+        Ok(Value::Symbol(
+            Symbol::new(
+                name,
+                match captures.name("q") {
+                    Some(capture) => match capture.as_str() {
+                        "'" => Quote::Single,
+                        "," => Quote::Eval,
+                        _ => return Err(ParserError::new(ParserErrorKind::NoMatchingParser, 0)),
+                    },
+                    None => Quote::None,
                 },
-                None => Quote::None,
+                captures.name("r").is_some(),
+            )
+            .in_package(package),
+        ))
+    }
+}
+
+/// What a character of source code belongs to.
+#[derive(Clone, Copy, PartialEq)]
+enum Lexeme {
+    Code,
+    String,
+    Char,
+    Comment,
+}
+
+/// Walks source code character by character, telling code apart from string literals, `?c`
+/// character literals and `;` comments, so brackets, quotes and semicolons inside those aren't
+/// mistaken for structure.
+struct Scanner<'a> {
+    characters: std::iter::Peekable<std::str::CharIndices<'a>>,
+    state: Lexeme,
+    escaping: bool,
+    previous: char,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(buffer: &'a str) -> Self {
+        Self {
+            characters: buffer.char_indices().peekable(),
+            state: Lexeme::Code,
+            escaping: false,
+            previous: ' ',
+        }
+    }
+
+    /// Whether the scanned buffer ended inside a string literal.
+    fn in_string(&self) -> bool {
+        self.state == Lexeme::String
+    }
+}
+
+impl<'a> Iterator for Scanner<'a> {
+    type Item = (usize, char, Lexeme);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (offset, character) = self.characters.next()?;
+
+        let lexeme = match self.state {
+            Lexeme::String => {
+                if self.escaping {
+                    self.escaping = false;
+                } else if character == '\\' {
+                    self.escaping = true;
+                } else if character == '"' {
+                    self.state = Lexeme::Code;
+                }
+
+                Lexeme::String
+            }
+            Lexeme::Comment if character == '\n' => {
+                self.state = Lexeme::Code;
+                Lexeme::Code
+            }
+            Lexeme::Comment => Lexeme::Comment,
+            // The character following `?`, or following `?\`.
+            Lexeme::Char => {
+                if character == '\\' && !self.escaping {
+                    self.escaping = true;
+                } else {
+                    self.escaping = false;
+                    self.state = Lexeme::Code;
+                }
+
+                Lexeme::Char
+            }
+            Lexeme::Code => match character {
+                '"' => {
+                    self.state = Lexeme::String;
+                    Lexeme::String
+                }
+                ';' => {
+                    self.state = Lexeme::Comment;
+                    Lexeme::Comment
+                }
+                // Only a `?` starting an element and followed by something makes a literal.
+                '?' if self.previous.is_whitespace() || "([".contains(self.previous) => {
+                    match self.characters.peek() {
+                        Some((_, next)) if !next.is_whitespace() => {
+                            self.state = Lexeme::Char;
+                            Lexeme::Char
+                        }
+                        _ => Lexeme::Code,
+                    }
+                }
+                _ => Lexeme::Code,
             },
-            captures.name("r").is_some(),
-        )))
+        };
+
+        // Anything but code is a single element, so nothing right after it starts a new one.
+        self.previous = if lexeme == Lexeme::Code {
+            character
+        } else {
+            '?'
+        };
+
+        Some((offset, character, lexeme))
     }
 }
 
-struct BracketParser;
+/// Parses `(...)` and `[...]` forms. In data mode, used for quasiquote templates, parenthesized
+/// forms that can't be function calls become plain lists instead of errors.
+struct BracketParser {
+    data: bool,
+}
 
 impl BracketParser {
-    fn new() -> Self {
-        Self
+    fn new(data: bool) -> Self {
+        Self { data }
     }
 }
 
 impl Parser for BracketParser {
-    fn has_next(&self, buffer: &String) -> ParserCheckResult {
+    fn has_next(&self, buffer: &str) -> ParserCheckResult {
         if buffer.len() < 2 {
-            return Err(ParserError::MalformedInput("Too short".into()));
+            return Err(ParserError::new(
+                ParserErrorKind::MalformedInput("Too short".into()),
+                0,
+            ));
         }
 
         if !['(', '['].contains(&buffer.chars().nth(0).unwrap()) {
-            return Err(ParserError::MalformedInput("Not a list".into()));
+            return Err(ParserError::new(
+                ParserErrorKind::MalformedInput("Not a list".into()),
+                0,
+            ));
         }
 
-        let mut matching: Vec<char> = Vec::new();
+        // Closing brackets we expect, along with the offset of their opening counterpart.
+        let mut matching: Vec<(char, usize)> = Vec::new();
+
+        for (offset, character, lexeme) in Scanner::new(buffer) {
+            if lexeme != Lexeme::Code {
+                continue;
+            }
 
-        for character in buffer.chars() {
             match character {
-                '(' => matching.push(')'),
-                '[' => matching.push(']'),
-                ')' | ']' => {
-                    if Some(character) != matching.pop() {
-                        return Err(ParserError::UnmatchedParentheses);
-                    }
+                '(' => matching.push((')', offset)),
+                '[' => matching.push((']', offset)),
+                ')' | ']' if Some(character) != matching.pop().map(|(closing, _)| closing) => {
+                    return Err(ParserError::new(
+                        ParserErrorKind::UnmatchedParentheses,
+                        offset,
+                    ));
                 }
                 _ => {}
             };
         }
 
-        if matching.is_empty() {
-            Ok(())
-        } else {
-            Err(ParserError::UnmatchedParentheses)
+        match matching.pop() {
+            None => Ok(()),
+            Some((_, offset)) => Err(ParserError::new(
+                ParserErrorKind::UnmatchedParentheses,
+                offset,
+            )),
         }
     }
 
-    fn parse(&self, buffer: &String) -> ParserResult {
-        let buffer = buffer[1..].to_string();
+    fn parse(&self, buffer: &str) -> ParserResult {
+        let buffer = &buffer[1..];
 
         let mut elements: Vec<Value> = Vec::new();
+        let mut dot: Option<(usize, usize)> = None;
 
-        let mut element = String::new();
-
-        for character in buffer.chars() {
-            if [' ', '\t', '\n', '\r', ')', ']'].contains(&character) {
-                // Skip random whitespaces.
-                if element.is_empty() {
-                    continue;
+        for (offset, element) in split_elements(buffer) {
+            if element == "." {
+                if dot.is_some() || elements.is_empty() {
+                    return Err(ParserError::new(ParserErrorKind::MisplacedDot, offset + 1));
                 }
 
-                match parse(&element) {
-                    Ok(value) => {
-                        elements.push(value);
-                        element = String::new();
-                    }
-                    Err(ParserError::NoMatchingParser) => element.push(character),
-                    Err(err) => return Err(err),
-                }
-            } else {
-                element.push(character);
+                dot = Some((elements.len(), offset + 1));
+                continue;
             }
-        }
 
-        if buffer.chars().last() == Some(')') {
-            if elements.is_empty() {
-                return Err(ParserError::EmptyFuncall);
+            match parse_form(element, self.data) {
+                Ok(value) => elements.push(value),
+                // One off because of `buffer[1..]`.
+                Err(err) => return Err(err.shifted(offset + 1)),
             }
+        }
 
-            if let Value::Symbol(symbol) = elements.first().unwrap() {
-                match symbol.quote {
-                    Quote::None => {
-                        let cdr = elements.iter().skip(1).cloned().collect();
-                        return Ok(Value::Funcall(symbol.clone(), cdr));
-                    }
-                    _ => {}
-                }
+        // Only `(a b . c)` or `[a b . c]`, with exactly one element after the dot, makes a dotted
+        // list. Both are written like `[...]`, evaluating their elements rather than calling one.
+        if let Some((index, offset)) = dot {
+            if index + 1 != elements.len() {
+                return Err(ParserError::new(ParserErrorKind::MisplacedDot, offset));
             }
 
-            Err(ParserError::InvalidFuncall)
-        } else {
+            let tail = elements.pop().unwrap();
+            return Ok(Value::Dotted(elements, Box::new(tail)));
+        }
+
+        if !buffer.ends_with(')') {
+            return Ok(Value::List(elements));
+        }
+
+        let callable = match elements.first() {
+            Some(Value::Symbol(symbol)) => symbol.quote == Quote::None,
+            // Allows calling a lambda in place: `((lambda [x] x) 1)`.
+            Some(Value::Funcall(_, _)) => true,
+            _ => false,
+        };
+
+        if callable {
+            let car = elements.remove(0);
+            Ok(Value::Funcall(Box::new(car), elements))
+        } else if self.data {
             Ok(Value::List(elements))
+        } else if elements.is_empty() {
+            Err(ParserError::new(ParserErrorKind::EmptyFuncall, 0))
+        } else {
+            Err(ParserError::new(ParserErrorKind::InvalidFuncall, 0))
+        }
+    }
+}
+
+/// Split the inside of a bracket form, up to its closing bracket, into elements along with their
+/// offsets. Whitespace and brackets inside string and character literals don't count.
+fn split_elements(buffer: &str) -> Vec<(usize, &str)> {
+    let mut elements: Vec<(usize, &str)> = Vec::new();
+
+    let mut start: Option<usize> = None;
+    let mut depth = 0;
+
+    for (offset, character, lexeme) in Scanner::new(buffer) {
+        let separator = match lexeme {
+            Lexeme::Code => character.is_whitespace(),
+            Lexeme::Comment => true,
+            _ => false,
+        };
+
+        if depth == 0 && (separator || (lexeme == Lexeme::Code && ")]".contains(character))) {
+            if let Some(start) = start.take() {
+                elements.push((start, &buffer[start..offset]));
+            }
+
+            if separator {
+                continue;
+            }
+
+            // The closing bracket of the form itself.
+            return elements;
+        }
+
+        if separator {
+            continue;
+        }
+
+        if start.is_none() {
+            start = Some(offset);
+        }
+
+        if lexeme == Lexeme::Code {
+            match character {
+                '(' | '[' => depth += 1,
+                ')' | ']' => depth -= 1,
+                _ => {}
+            }
         }
     }
+
+    if let Some(start) = start {
+        elements.push((start, &buffer[start..]));
+    }
+
+    elements
+}
+
+/// Parses a prefixed bracket form like `'(...)` into `(function ...)`.
+struct PrefixParser {
+    prefix: char,
+    function: &'static str,
+    data: bool,
+}
+
+impl PrefixParser {
+    fn new(prefix: char, function: &'static str, data: bool) -> Self {
+        Self {
+            prefix,
+            function,
+            data,
+        }
+    }
+
+    fn brackets(&self) -> BracketParser {
+        BracketParser::new(self.data)
+    }
+}
+
+impl Parser for PrefixParser {
+    fn has_next(&self, buffer: &str) -> ParserCheckResult {
+        let mut characters = buffer.chars();
+
+        if characters.next() != Some(self.prefix) || !matches!(characters.next(), Some('(' | '[')) {
+            return Err(ParserError::new(
+                ParserErrorKind::MalformedInput("Not a prefixed list".into()),
+                0,
+            ));
+        }
+
+        self.brackets()
+            .has_next(&buffer[1..])
+            .map_err(|err| err.shifted(1))
+    }
+
+    fn parse(&self, buffer: &str) -> ParserResult {
+        let value = self
+            .brackets()
+            .parse(&buffer[1..])
+            .map_err(|err| err.shifted(1))?;
+
+        Ok(Value::Funcall(
+            Box::new(Value::Symbol(Symbol::from_str(self.function))),
+            vec![value],
+        ))
+    }
+}
+
+/// Parses `#'name` into `(function name)`.
+struct FunctionParser {
+    symbols: SymbolParser,
+}
+
+impl FunctionParser {
+    fn new() -> Self {
+        Self {
+            symbols: SymbolParser::new(),
+        }
+    }
+}
+
+impl Parser for FunctionParser {
+    fn has_next(&self, buffer: &str) -> ParserCheckResult {
+        match buffer.strip_prefix("#'") {
+            Some(name) if !name.starts_with(['\'', ',']) => {
+                self.symbols.has_next(name).map_err(|err| err.shifted(2))
+            }
+            _ => Err(ParserError::new(
+                ParserErrorKind::MalformedInput("Not a function reference".into()),
+                0,
+            )),
+        }
+    }
+
+    fn parse(&self, buffer: &str) -> ParserResult {
+        let symbol = self
+            .symbols
+            .parse(&buffer[2..])
+            .map_err(|err| err.shifted(2))?;
+
+        Ok(Value::Funcall(
+            Box::new(Value::Symbol(Symbol::from_str("function"))),
+            vec![symbol],
+        ))
+    }
 }
 
 struct SpecialParser {
@@ -220,18 +713,19 @@ impl SpecialParser {
 }
 
 impl Parser for SpecialParser {
-    fn has_next(&self, buffer: &String) -> ParserCheckResult {
-        if self.mappings.contains_key(buffer.as_str()) {
+    fn has_next(&self, buffer: &str) -> ParserCheckResult {
+        if self.mappings.contains_key(buffer) {
             Ok(())
         } else {
-            Err(ParserError::MalformedInput(
-                "Not a special token".to_string(),
+            Err(ParserError::new(
+                ParserErrorKind::MalformedInput("Not a special token".to_string()),
+                0,
             ))
         }
     }
 
-    fn parse(&self, buffer: &String) -> ParserResult {
-        Ok(self.mappings.get(buffer.as_str()).unwrap().clone())
+    fn parse(&self, buffer: &str) -> ParserResult {
+        Ok(self.mappings.get(buffer).unwrap().clone())
     }
 }
 
@@ -244,20 +738,18 @@ impl StringParser {
 }
 
 impl Parser for StringParser {
-    fn has_next(&self, buffer: &String) -> ParserCheckResult {
-        if buffer.len() >= 2
-            && buffer.chars().nth(0) == Some('"')
-            && buffer.chars().last() == Some('"')
-        {
+    fn has_next(&self, buffer: &str) -> ParserCheckResult {
+        if buffer.len() >= 2 && buffer.chars().nth(0) == Some('"') && buffer.ends_with('"') {
             Ok(())
         } else {
-            Err(ParserError::MalformedInput(
-                "Must be enclosed in double quotes".into(),
+            Err(ParserError::new(
+                ParserErrorKind::MalformedInput("Must be enclosed in double quotes".into()),
+                0,
             ))
         }
     }
 
-    fn parse(&self, buffer: &String) -> ParserResult {
+    fn parse(&self, buffer: &str) -> ParserResult {
         let mut escape_sequences: HashMap<char, char> = HashMap::new();
 
         escape_sequences.insert('"', '"');
@@ -268,23 +760,29 @@ impl Parser for StringParser {
         let mut escaping = false;
         let mut string = String::new();
 
-        for (index, character) in buffer[1..].chars().enumerate() {
+        for (index, character) in buffer[1..].char_indices() {
+            // One off because of `buffer[1..]`.
+            let offset = index + 1;
+
             if escaping {
                 if let Some(result) = escape_sequences.get(&character) {
                     string.push(*result);
                     escaping = false;
                 } else {
-                    return Err(ParserError::InvalidEscapeSequence(character));
+                    return Err(ParserError::new(
+                        ParserErrorKind::InvalidEscapeSequence(character),
+                        offset,
+                    ));
                 }
             } else if character == '\\' {
                 escaping = true;
             } else if character == '"' {
-                // One off because of `buffer[1..]`.
-                if index == buffer.len() - 2 {
+                if offset == buffer.len() - 1 {
                     return Ok(Value::String(string));
                 } else {
-                    return Err(ParserError::MalformedInput(
-                        "String literal closed early".into(),
+                    return Err(ParserError::new(
+                        ParserErrorKind::MalformedInput("String literal closed early".into()),
+                        offset,
                     ));
                 }
             } else {
@@ -292,28 +790,112 @@ impl Parser for StringParser {
             }
         }
 
-        // Never reached due to conditions in `.has_next()`.
-        Err(ParserError::MalformedInput(
-            "Reached the end of string literal".into(),
+        Err(ParserError::new(
+            ParserErrorKind::MalformedInput("Reached the end of string literal".into()),
+            buffer.len(),
         ))
     }
 }
 
-pub fn parse(buffer: &String) -> ParserResult {
+/// Blank out `;` line comments, leaving string and character literals intact and offsets
+/// unchanged.
+fn strip_comments(buffer: &str) -> String {
+    Scanner::new(buffer)
+        .map(|(_, character, lexeme)| match lexeme {
+            Lexeme::Comment => " ".repeat(character.len_utf8()),
+            _ => character.to_string(),
+        })
+        .collect()
+}
+
+/// Parse a buffer, reporting error offsets relative to its start.
+fn parse_relative(buffer: &str) -> ParserResult {
+    parse_form(buffer, false)
+}
+
+/// Like `parse_relative`, but bracket forms are parsed in data mode if `data` is set.
+fn parse_form(buffer: &str, data: bool) -> ParserResult {
+    let stripped = strip_comments(buffer);
+    let trimmed = stripped.trim_start();
+    let leading = stripped.len() - trimmed.len();
+    let buffer = trimmed.trim_end();
+
     let parsers: Vec<Box<dyn Parser>> = vec![
         Box::new(IntegerParser::new()),
+        Box::new(FloatParser::new()),
+        Box::new(RatioParser::new()),
         Box::new(SpecialParser::new()),
         Box::new(StringParser::new()),
+        Box::new(CharParser::new()),
+        Box::new(FunctionParser::new()),
         Box::new(SymbolParser::new()),
-        Box::new(BracketParser::new()),
+        Box::new(PrefixParser::new('\'', "quote", false)),
+        Box::new(PrefixParser::new('`', "quasiquote", true)),
+        Box::new(PrefixParser::new(',', "unquote", false)),
+        Box::new(BracketParser::new(data)),
     ];
 
     for parser in parsers {
         match parser.has_next(buffer) {
-            Ok(_) => return parser.parse(buffer),
+            Ok(_) => return parser.parse(buffer).map_err(|err| err.shifted(leading)),
+            // Unbalanced lists can't be anything else, so report that precisely.
+            Err(err) if err.kind == ParserErrorKind::UnmatchedParentheses => {
+                return Err(err.shifted(leading))
+            }
             Err(_) => {}
         }
     }
 
-    Err(ParserError::NoMatchingParser)
+    Err(ParserError::new(ParserErrorKind::NoMatchingParser, leading))
+}
+
+/// Check if the buffer is the beginning of a form, with brackets or a string literal left open.
+/// Malformed input, like a stray closing bracket, isn't considered incomplete.
+pub fn is_incomplete(buffer: &str) -> bool {
+    let mut closing: Vec<char> = Vec::new();
+    let mut scanner = Scanner::new(buffer);
+
+    for (_, character, lexeme) in &mut scanner {
+        if lexeme != Lexeme::Code {
+            continue;
+        }
+
+        match character {
+            '(' => closing.push(')'),
+            '[' => closing.push(']'),
+            ')' | ']' if closing.pop() != Some(character) => return false,
+            _ => {}
+        }
+    }
+
+    scanner.in_string() || !closing.is_empty()
+}
+
+pub fn parse(buffer: &str) -> ParserResult {
+    parse_relative(buffer).map_err(|err| err.locate(buffer))
+}
+
+/// Parse every top-level form in the buffer, like the contents of a file.
+pub fn parse_all(buffer: &str) -> Result<Vec<Value>, ParserError> {
+    let stripped = strip_comments(buffer);
+    let elements = split_elements(&stripped);
+
+    // Splitting stops early at a closing bracket without an opening one.
+    let end = match elements.last() {
+        Some((offset, element)) => offset + element.len(),
+        None => 0,
+    };
+
+    if let Some(offset) = stripped[end..].find(|character: char| !character.is_whitespace()) {
+        return Err(
+            ParserError::new(ParserErrorKind::UnmatchedParentheses, end + offset).locate(buffer),
+        );
+    }
+
+    elements
+        .into_iter()
+        .map(|(offset, element)| {
+            parse_relative(element).map_err(|err| err.shifted(offset).locate(buffer))
+        })
+        .collect()
 }
@@ -1,12 +1,20 @@
 use std::io;
-use std::io::Write;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::time::Instant;
 
-use crate::crisp::Environment;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
 
-fn read_line() -> io::Result<String> {
+use crate::crisp::{Environment, Value};
+use crate::parsers::is_incomplete;
+
+pub fn read_line(input: &mut dyn BufRead) -> io::Result<Option<String>> {
     let mut buffer = String::new();
 
-    io::stdin().read_line(&mut buffer)?;
+    if input.read_line(&mut buffer)? == 0 {
+        return Ok(None);
+    }
 
     if buffer.ends_with("\n") {
         buffer.pop();
@@ -16,26 +24,223 @@ fn read_line() -> io::Result<String> {
         }
     }
 
-    Ok(buffer)
+    Ok(Some(buffer))
+}
+
+/// Where the REPL gets its input from.
+pub trait LineEditor {
+    /// Show `prompt` and read a line, or `None` at the end of input.
+    fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>>;
+
+    fn add_history(&mut self, line: &str);
+}
+
+/// A line editor with history, persisted to `~/.crisp_history`.
+struct Rustyline {
+    editor: DefaultEditor,
+    history: Option<PathBuf>,
+}
+
+impl Rustyline {
+    fn new() -> io::Result<Self> {
+        let mut editor = DefaultEditor::new().map_err(to_io_error)?;
+        let history = dirs::home_dir().map(|home| home.join(".crisp_history"));
+
+        if let Some(path) = &history {
+            // There's no history on the first run.
+            let _ = editor.load_history(path);
+        }
+
+        Ok(Self { editor, history })
+    }
+
+    fn save_history(&mut self) -> io::Result<()> {
+        match &self.history {
+            Some(path) => self.editor.save_history(path).map_err(to_io_error),
+            None => Ok(()),
+        }
+    }
+}
+
+impl LineEditor for Rustyline {
+    fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        match self.editor.readline(prompt) {
+            Ok(line) => Ok(Some(line)),
+            // Ctrl-C discards the current line like in a shell.
+            Err(ReadlineError::Interrupted) => Ok(Some(String::new())),
+            Err(ReadlineError::Eof) => Ok(None),
+            Err(error) => Err(to_io_error(error)),
+        }
+    }
+
+    fn add_history(&mut self, line: &str) {
+        let _ = self.editor.add_history_entry(line);
+    }
+}
+
+fn to_io_error(error: ReadlineError) -> io::Error {
+    match error {
+        ReadlineError::Io(error) => error,
+        error => io::Error::other(error.to_string()),
+    }
+}
+
+pub fn mainloop(environment: Environment) -> io::Result<()> {
+    let mut editor = Rustyline::new()?;
+    let result = Repl::new(environment).run(&mut editor);
+
+    editor.save_history()?;
+
+    result
+}
+
+/// Read lines until they make up a complete form, or `None` at the end of input.
+pub fn read_form(editor: &mut dyn LineEditor) -> io::Result<Option<String>> {
+    let mut form = match editor.read_line("> ")? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+
+    while is_incomplete(&form) {
+        match editor.read_line(".. ")? {
+            Some(line) => {
+                form.push('\n');
+                form.push_str(&line);
+            }
+            // Let the parser report what's missing.
+            None => break,
+        }
+    }
+
+    Ok(Some(form))
+}
+
+/// How many of the innermost callers to show under an error.
+const BACKTRACE_LIMIT: usize = 10;
+
+/// Append the innermost callers of a backtrace to an error message.
+pub fn with_backtrace(message: String, backtrace: &[String]) -> String {
+    if backtrace.is_empty() {
+        return message;
+    }
+
+    let mut lines = vec![message, "Backtrace:".to_string()];
+
+    for caller in backtrace.iter().take(BACKTRACE_LIMIT) {
+        lines.push(format!("  in {}", caller));
+    }
+
+    if backtrace.len() > BACKTRACE_LIMIT {
+        lines.push(format!("  ... {} more", backtrace.len() - BACKTRACE_LIMIT));
+    }
+
+    lines.join("\n")
+}
+
+const HELP: &str = "\
+:load <file>    evaluate a file in the current session
+:env            list variables and functions
+:time [on|off]  show how long each evaluation takes
+:help           show this message
+exit, quit      leave the REPL";
+
+pub struct Repl {
+    pub environment: Environment,
+    timing: bool,
 }
 
-pub fn mainloop() -> io::Result<()> {
-    let mut environment = Environment::new_configured();
+impl Repl {
+    pub fn new(environment: Environment) -> Self {
+        Self {
+            environment,
+            timing: false,
+        }
+    }
+
+    pub fn run(&mut self, editor: &mut dyn LineEditor) -> io::Result<()> {
+        loop {
+            let input = match read_form(editor)? {
+                Some(input) => input,
+                None => {
+                    println!();
+                    return Ok(());
+                }
+            };
+
+            if input.trim().is_empty() {
+                continue;
+            }
+
+            editor.add_history(&input);
+
+            if ["exit", "quit"].contains(&input.as_str()) {
+                println!("Goodbye!");
+                return Ok(());
+            }
+
+            if input.starts_with(':') {
+                println!("{}", self.command(&input));
+            } else {
+                println!("{}", self.eval(&input));
+            }
+
+            io::stdout().flush()?;
+        }
+    }
 
-    loop {
-        print!("> ");
-        io::stdout().flush()?;
+    /// Evaluate the input, returning what to print: the value of each form on a line of its own.
+    pub fn eval(&mut self, input: &str) -> String {
+        let start = Instant::now();
 
-        let input = read_line()?;
+        let output = match self.environment.eval_all(input) {
+            Ok(values) => {
+                let lines: Vec<String> = values.iter().map(Value::to_string).collect();
+                lines.join("\n")
+            }
+            Err(error) => with_backtrace(error.to_string(), &self.environment.take_backtrace()),
+        };
 
-        if ["exit", "quit"].contains(&input.as_str()) {
-            println!("Goodbye!");
-            return Ok(());
+        if self.timing {
+            format!("{}\n; evaluated in {:.1?}", output, start.elapsed())
+        } else {
+            output
         }
+    }
+
+    /// Handle a `:`-prefixed REPL command, returning what to print.
+    pub fn command(&mut self, input: &str) -> String {
+        let mut words = input.split_whitespace();
+
+        match (words.next().unwrap_or(""), words.next()) {
+            (":load", Some(file)) => match self.environment.eval_file(file.to_string()) {
+                Ok(value) => value.to_string(),
+                Err(error) => with_backtrace(error.to_string(), &self.environment.take_backtrace()),
+            },
+            (":load", None) => "Usage: :load <file>".to_string(),
+            (":env", _) => {
+                let mut lines = vec!["Variables:".to_string()];
+
+                for (name, value) in self.environment.top_level().bindings() {
+                    lines.push(format!("  {} = {}", name, value));
+                }
+
+                lines.push("Functions:".to_string());
+                lines.push(format!("  {}", self.environment.function_names().join(" ")));
+
+                lines.join("\n")
+            }
+            (":time", argument) => {
+                match argument {
+                    Some("on") => self.timing = true,
+                    Some("off") => self.timing = false,
+                    None => (),
+                    Some(_) => return "Usage: :time [on|off]".to_string(),
+                }
 
-        match environment.eval(&input) {
-            Ok(value) => println!("{:?}", value),
-            Err(error) => println!("{:?}", error),
+                format!("Timing is {}", if self.timing { "on" } else { "off" })
+            }
+            (":help", _) => HELP.to_string(),
+            (command, _) => format!("Unknown command: {}. Try :help", command),
         }
     }
 }
@@ -1,7 +1,39 @@
-use crate::crisp::{Environment, Integer, Quote, Symbol, Value};
+use crate::crisp::{Environment, EvalError, Float, Integer, Quote, Symbol, Value, STACK_SIZE};
+use crate::parsers::{ParserError, ParserErrorKind};
+use crate::repl::{LineEditor, Repl};
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::io::Write;
+use std::rc::Rc;
 
 fn parse(buffer: &str) -> crate::parsers::ParserResult {
-    crate::parsers::parse(&buffer.into())
+    crate::parsers::parse(buffer)
+}
+
+/// A sink that can still be read after a clone of it is handed to the environment.
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Output {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+
+    fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buffer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 #[test]
@@ -25,10 +57,18 @@ fn integer() {
     }
 
     assert_eq!(parse("+1000").unwrap(), Value::Integer(1000));
+    assert_eq!(parse("10000000000").unwrap(), Value::Integer(10000000000));
+    assert_eq!(
+        parse("-9223372036854775808").unwrap(),
+        Value::Integer(Integer::MIN)
+    );
 
-    assert!(parse("100000000000").is_err());
-    assert!(parse("-99999999999").is_err());
-    assert!(parse("+99999999999").is_err());
+    // Large literals are bigints with the `bignum` feature.
+    if cfg!(not(feature = "bignum")) {
+        assert!(parse("100000000000000000000").is_err());
+        assert!(parse("-99999999999999999999").is_err());
+        assert!(parse("+9223372036854775808").is_err());
+    }
 }
 
 #[test]
@@ -75,7 +115,7 @@ fn symbol() {
         Ok(Value::Symbol(symbol)) => {
             assert_eq!(symbol.as_str(), "hello");
             assert_eq!(symbol.quote, Quote::Single);
-            assert_eq!(symbol.rest, false);
+            assert!(!symbol.rest);
         }
         _ => panic!("Failed to parse 'hello as symbol"),
     }
@@ -84,7 +124,7 @@ fn symbol() {
         Ok(Value::Symbol(symbol)) => {
             assert_eq!(symbol.as_str(), "bye");
             assert_eq!(symbol.quote, Quote::Eval);
-            assert_eq!(symbol.rest, false);
+            assert!(!symbol.rest);
         }
         _ => panic!("Failed to parse ,bye as symbol"),
     }
@@ -93,7 +133,7 @@ fn symbol() {
         Ok(Value::Symbol(symbol)) => {
             assert_eq!(symbol.as_str(), "actually-no");
             assert_eq!(symbol.quote, Quote::Single);
-            assert_eq!(symbol.rest, true);
+            assert!(symbol.rest);
         }
         _ => panic!("Failed to parse 'actually-no... as symbol"),
     }
@@ -109,9 +149,9 @@ fn symbol() {
 
     environment.top_level().put_str("the-answer", value.clone());
 
-    assert_eq!(environment.eval(&"the-answer".into()).unwrap(), value);
+    assert_eq!(environment.eval("the-answer").unwrap(), value);
     assert_eq!(
-        environment.eval(&"'the-answer".into()).unwrap(),
+        environment.eval("'the-answer").unwrap(),
         Value::Symbol(Symbol::new("the-answer".into(), Quote::Single, false))
     );
 }
@@ -132,6 +172,76 @@ fn list() {
     );
 }
 
+#[test]
+fn list_elements() {
+    assert_eq!(
+        parse("[-1 -2]").unwrap(),
+        Value::List(vec![Value::Integer(-1), Value::Integer(-2)])
+    );
+    assert_eq!(
+        parse("[\"a b\" \"c\"]").unwrap(),
+        Value::List(vec![Value::String("a b".into()), Value::String("c".into())])
+    );
+    assert_eq!(
+        parse("[[1] [2]]").unwrap(),
+        Value::List(vec![
+            Value::List(vec![Value::Integer(1)]),
+            Value::List(vec![Value::Integer(2)])
+        ])
+    );
+    assert_eq!(parse("[ 1\n\t[2  3] ]").unwrap().to_string(), "[1 [2 3]]");
+    assert_eq!(
+        parse("[\"a  b\" ?\\s \"\\\" c\"]").unwrap(),
+        Value::List(vec![
+            Value::String("a  b".into()),
+            Value::Char(' '),
+            Value::String("\" c".into())
+        ])
+    );
+
+    // Elements that can't be parsed are reported, not glued to their neighbours.
+    assert_eq!(
+        parse("[1 \"two\\q\" 3]").unwrap_err().kind,
+        ParserErrorKind::InvalidEscapeSequence('q')
+    );
+    assert!(parse("(f ',x)").is_err());
+}
+
+#[test]
+fn brackets_in_literals() {
+    assert_eq!(
+        parse("[\"(\" \")\"]").unwrap(),
+        Value::List(vec![Value::String("(".into()), Value::String(")".into())])
+    );
+    assert_eq!(
+        parse("[\"[unbalanced\"]").unwrap(),
+        Value::List(vec![Value::String("[unbalanced".into())])
+    );
+    assert_eq!(
+        parse("[\"a)b\" \"\\\"]\"]").unwrap(),
+        Value::List(vec![
+            Value::String("a)b".into()),
+            Value::String("\"]".into())
+        ])
+    );
+    assert_eq!(
+        parse("[?( ?\\] ?]]").unwrap(),
+        Value::List(vec![Value::Char('('), Value::Char(']'), Value::Char(']')])
+    );
+
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(
+        environment.eval_str("(concat \"(\" \"a]\")").unwrap(),
+        Value::String("(a]".into())
+    );
+
+    assert_eq!(
+        parse("[\"(\" ]]").unwrap_err().kind,
+        ParserErrorKind::UnmatchedParentheses
+    );
+}
+
 #[test]
 fn funcall() {
     let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
@@ -139,6 +249,8 @@ fn funcall() {
     assert_eq!(eval("(+ 1 2 3)"), Value::Integer(6));
     assert_eq!(eval("(+ 10 -5)"), Value::Integer(5));
     assert_eq!(eval("(- 10)"), Value::Integer(-10));
+    assert_eq!(eval("(- (+ 1 2))"), Value::Integer(-3));
+    assert_eq!(eval("(progn (let 'x 4) (- x))"), Value::Integer(-4));
     assert_eq!(eval("(* 2 -2)"), Value::Integer(-4));
     assert_eq!(eval("(/ 10 2)"), Value::Integer(5));
 
@@ -191,7 +303,9 @@ fn factorial() {
     environment.top_level().put_str("input", Value::Integer(5));
 
     assert_eq!(
-        environment.eval_file("test/factorial.crisp".into()).unwrap(),
+        environment
+            .eval_file("test/factorial.crisp".into())
+            .unwrap(),
         Value::Integer(120)
     );
 }
@@ -200,7 +314,9 @@ fn factorial() {
 fn fibonacci() {
     let mut environment = Environment::new_configured();
 
-    environment.eval_file("test/fibonacci.crisp".into()).unwrap();
+    environment
+        .eval_file("test/fibonacci.crisp".into())
+        .unwrap();
 
     assert_eq!(
         environment.eval_str("(fibonacci 5)").unwrap(),
@@ -213,7 +329,9 @@ fn quoted_args() {
     let mut environment = Environment::new_configured();
 
     assert_eq!(
-        environment.eval_file("test/quoted-args.crisp".into()).unwrap(),
+        environment
+            .eval_file("test/quoted-args.crisp".into())
+            .unwrap(),
         Value::Integer(120)
     );
 }
@@ -222,7 +340,9 @@ fn quoted_args() {
 fn rest_args() {
     let mut environment = Environment::new_configured();
 
-    environment.eval_file("test/rest-args.crisp".into()).unwrap();
+    environment
+        .eval_file("test/rest-args.crisp".into())
+        .unwrap();
 
     assert_eq!(
         environment.eval_str("(rcar 1 2 3)").unwrap(),
@@ -235,3 +355,3263 @@ fn rest_args() {
 
     assert!(environment.eval_str("(defun buggy [a... b...])").is_err());
 }
+
+#[test]
+fn division_by_zero() {
+    let mut environment = Environment::new_configured();
+
+    assert!(environment.eval_str("(/ 5 0)").is_err());
+    assert!(environment.eval_str("(/ 10 2 0)").is_err());
+
+    // The environment is still usable after the failed division.
+    assert_eq!(environment.eval_str("(/ 10 2)").unwrap(), Value::Integer(5));
+}
+
+#[test]
+fn arithmetic_overflow() {
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(
+        environment.eval_str("(+ 9223372036854775806 1)").unwrap(),
+        Value::Integer(Integer::MAX)
+    );
+    assert_eq!(
+        environment.eval_str("(* 3037000499 3037000499)").unwrap(),
+        Value::Integer(9223372030926249001)
+    );
+    assert_eq!(
+        environment.eval_str("(* 100000 100000)").unwrap(),
+        Value::Integer(10000000000)
+    );
+
+    // Overflowing integers are promoted to bigints with the `bignum` feature.
+    if cfg!(not(feature = "bignum")) {
+        assert!(environment.eval_str("(+ 9223372036854775807 1)").is_err());
+        assert!(environment.eval_str("(- -9223372036854775807 2)").is_err());
+        assert!(environment.eval_str("(* 10000000000 10000000000)").is_err());
+        assert!(environment
+            .eval_str("(/ (- -9223372036854775807 1) -1)")
+            .is_err());
+    }
+}
+
+#[test]
+fn float() {
+    let floats: Vec<(&str, Float)> = vec![
+        ("2.75", 2.75),
+        ("-0.5", -0.5),
+        ("+2.0", 2.0),
+        ("1e10", 1e10),
+        ("2.5E-3", 2.5e-3),
+    ];
+
+    for (buffer, float) in floats.iter() {
+        assert_eq!(parse(buffer).unwrap(), Value::Float(*float));
+    }
+
+    assert!(parse("1e999").is_err());
+
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(+ 1 2.5)"), Value::Float(3.5));
+    assert_eq!(eval("(/ 7.0 2)"), Value::Float(3.5));
+    assert_eq!(eval("(* 2 0.5)"), Value::Float(1.0));
+    assert_eq!(eval("(- 1.5)"), Value::Float(-1.5));
+    assert_eq!(eval("(/ 7 2)"), Value::Integer(3));
+
+    assert!(Environment::new_configured().eval_str("(/ 1.0 0)").is_err());
+}
+
+#[test]
+fn comparison() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(< 1 2)"), Value::T);
+    assert_eq!(eval("(< 1 2 3)"), Value::T);
+    assert_eq!(eval("(< 1 3 2)"), Value::Nil);
+    assert_eq!(eval("(< 1 1)"), Value::Nil);
+    assert_eq!(eval("(<= 1 1 2)"), Value::T);
+    assert_eq!(eval("(> 3 2 1)"), Value::T);
+    assert_eq!(eval("(> 3 1 2)"), Value::Nil);
+    assert_eq!(eval("(>= 3 3 1)"), Value::T);
+    assert_eq!(eval("(< 1 1.5 2)"), Value::T);
+
+    let mut environment = Environment::new_configured();
+
+    assert!(environment.eval_str("(< 1)").is_err());
+    assert!(environment.eval_str("(< 1 \"two\")").is_err());
+}
+
+#[test]
+fn equality() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(= 1 1)"), Value::T);
+    assert_eq!(eval("(= 1 1 1)"), Value::T);
+    assert_eq!(eval("(= 1 1 2)"), Value::Nil);
+    assert_eq!(eval("(= 2 1 1)"), Value::Nil);
+    assert_eq!(eval("(= 1)"), Value::T);
+    assert_eq!(eval("(= t t t)"), Value::T);
+    assert_eq!(eval("(= nil nil nil)"), Value::T);
+    assert_eq!(eval("(= \"a\" \"a\" \"a\")"), Value::T);
+
+    // Numbers are compared by value.
+    assert_eq!(eval("(= 1 1.0 2/2)"), Value::T);
+    assert_eq!(eval("(= 1/2 0.5)"), Value::T);
+    assert_eq!(eval("(= 1 \"1\")"), Value::Nil);
+
+    // The rest isn't evaluated once an arg differs.
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(
+        environment.eval_str("(= 1 2 (set 'ran t))").unwrap(),
+        Value::Nil
+    );
+    assert!(environment.eval_str("ran").is_err());
+    assert!(environment.eval_str("(=)").is_err());
+}
+
+#[test]
+fn inequality() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(/= 1 2)"), Value::T);
+    assert_eq!(eval("(/= 1 2 3)"), Value::T);
+    assert_eq!(eval("(/= 1 1 2)"), Value::Nil);
+    assert_eq!(eval("(/= 1 2 1)"), Value::Nil);
+    assert_eq!(eval("(/= 1 2 2)"), Value::Nil);
+    assert_eq!(eval("(/= 1)"), Value::T);
+    assert_eq!(eval("(/= 1 1.0)"), Value::Nil);
+    assert_eq!(eval("(/= 'a 'b \"a\")"), Value::T);
+
+    assert!(Environment::new_configured().eval_str("(/=)").is_err());
+}
+
+#[test]
+fn modulo() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(mod 10 3)"), Value::Integer(1));
+    assert_eq!(eval("(% 10 3)"), Value::Integer(1));
+    assert_eq!(eval("(mod -7 3)"), Value::Integer(2));
+    assert_eq!(eval("(mod 7 -3)"), Value::Integer(-2));
+    assert_eq!(eval("(mod 9 3)"), Value::Integer(0));
+
+    let mut environment = Environment::new_configured();
+
+    assert!(environment.eval_str("(mod 10 0)").is_err());
+    assert!(environment.eval_str("(mod 10 2.5)").is_err());
+}
+
+#[test]
+fn rounding() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+    let both = |buffer: &str| {
+        let buffer = format!("(multiple-value-bind [q r] {} [q r])", buffer);
+        Environment::new_configured()
+            .eval_str(&buffer)
+            .unwrap()
+            .to_string()
+    };
+
+    assert_eq!(eval("(floor 7 2)"), Value::Integer(3));
+    assert_eq!(eval("(ceiling 7 2)"), Value::Integer(4));
+    assert_eq!(eval("(round 7 2)"), Value::Integer(4));
+    assert_eq!(eval("(truncate 7 2)"), Value::Integer(3));
+
+    // The modes differ for negative quotients.
+    assert_eq!(both("(floor -7 2)"), "[-4 1]");
+    assert_eq!(both("(ceiling -7 2)"), "[-3 -1]");
+    assert_eq!(both("(truncate -7 2)"), "[-3 -1]");
+    assert_eq!(both("(floor 7 -2)"), "[-4 -1]");
+    assert_eq!(both("(ceiling 7 -2)"), "[-3 1]");
+    assert_eq!(both("(floor -7 -2)"), "[3 -1]");
+
+    // Ties round to even.
+    assert_eq!(both("(round 5 2)"), "[2 1]");
+    assert_eq!(both("(round -5 2)"), "[-2 -1]");
+    assert_eq!(both("(round -7 2)"), "[-4 1]");
+    assert_eq!(both("(round 2.5)"), "[2 0.5]");
+
+    // The divisor defaults to 1, and the remainder is exact when the operands are.
+    assert_eq!(both("(floor 7/2)"), "[3 1/2]");
+    assert_eq!(both("(ceiling 1/3 1/6)"), "[2 0]");
+    assert_eq!(both("(floor 7.5 2)"), "[3 1.5]");
+    assert_eq!(both("(truncate -2.5)"), "[-2 -0.5]");
+    assert_eq!(both("(floor 6 3)"), "[2 0]");
+
+    let mut environment = Environment::new_configured();
+
+    assert!(matches!(
+        environment.eval_str("(floor 1 0)"),
+        Err(EvalError::DivisionByZero(_))
+    ));
+    if cfg!(not(feature = "bignum")) {
+        assert!(matches!(
+            environment.eval_str("(floor 1.0e300)"),
+            Err(EvalError::ArithmeticOverflow(_))
+        ));
+    }
+    assert!(environment.eval_str("(floor)").is_err());
+    assert!(environment.eval_str("(floor 1 2 3)").is_err());
+    assert!(environment.eval_str("(floor \"1\" 2)").is_err());
+}
+
+#[test]
+fn cons() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+    let integers =
+        |integers: Vec<Integer>| Value::List(integers.into_iter().map(Value::Integer).collect());
+
+    assert_eq!(eval("(cons 1 [2 3])"), integers(vec![1, 2, 3]));
+    assert_eq!(eval("(cons 1 [])"), integers(vec![1]));
+    assert_eq!(eval("(cons 1 nil)"), integers(vec![1]));
+    assert_eq!(eval("(cons (+ 1 1) (cons 3 []))"), integers(vec![2, 3]));
+
+    assert_eq!(eval("(list 1 2 3)"), integers(vec![1, 2, 3]));
+    assert_eq!(eval("(list (+ 1 2) 4)"), integers(vec![3, 4]));
+    assert_eq!(eval("(list)"), integers(vec![]));
+
+    let mut environment = Environment::new_configured();
+
+    assert!(environment.eval_str("(cons 1)").is_err());
+}
+
+#[test]
+fn dotted_pairs() {
+    let mut environment = Environment::new_configured();
+    let mut eval = |buffer| environment.eval_str(buffer).unwrap().to_string();
+
+    assert_eq!(eval("(cons 1 2)"), "(1 . 2)");
+    assert_eq!(eval("(cons 1 (cons 2 3))"), "(1 2 . 3)");
+    assert_eq!(eval("(cons 1 (cons 2 nil))"), "[1 2]");
+    assert_eq!(eval("[(+ 1 2) . (* 2 2)]"), "(3 . 4)");
+    assert_eq!(eval("'[a b . c]"), "(a b . c)");
+    assert_eq!(eval("[1 . [2 3]]"), "[1 2 3]");
+
+    assert_eq!(eval("(car (cons 1 2))"), "1");
+    assert_eq!(eval("(cdr (cons 1 2))"), "2");
+    assert_eq!(eval("(cdr [1 2 . 3])"), "(2 . 3)");
+    assert_eq!(eval("(listp (cons 1 2))"), "t");
+    assert_eq!(eval("(apply 'car [(cons 1 2)])"), "1");
+
+    assert_eq!(
+        Environment::new_configured()
+            .eval_str("(cons 1 2)")
+            .unwrap(),
+        Value::Dotted(vec![Value::Integer(1)], Box::new(Value::Integer(2)))
+    );
+
+    // Printed dotted lists read back as they were.
+    assert_eq!(eval("(read (to-string (cons 1 2)))"), "(1 . 2)");
+    assert_eq!(eval("(= (read (to-string (cons 1 2))) (cons 1 2))"), "t");
+    assert_eq!(eval("'(1 2 . 3)"), "(1 2 . 3)");
+    assert_eq!(eval("'(a . b)"), "(a . b)");
+    assert_eq!(eval("(cdr '(1 2 . 3))"), "(2 . 3)");
+    assert_eq!(eval("((+ 1 2) . 4)"), "(3 . 4)");
+
+    for misplaced in [
+        "[. 1]",
+        "[1 .]",
+        "[1 . 2 3]",
+        "[1 . 2 . 3]",
+        "(1 . 2 3)",
+        "(. 1)",
+    ] {
+        assert_eq!(
+            parse(misplaced).unwrap_err().kind,
+            ParserErrorKind::MisplacedDot,
+            "{}",
+            misplaced
+        );
+    }
+}
+
+#[test]
+fn length() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(length [1 2 3])"), Value::Integer(3));
+    assert_eq!(eval("(length [])"), Value::Integer(0));
+    assert_eq!(eval("(length \"abc\")"), Value::Integer(3));
+    assert_eq!(eval("(length \"\")"), Value::Integer(0));
+    assert_eq!(eval("(length nil)"), Value::Integer(0));
+    assert_eq!(eval("(length (list 1 2))"), Value::Integer(2));
+
+    let mut environment = Environment::new_configured();
+
+    assert!(environment.eval_str("(length 5)").is_err());
+    assert!(matches!(
+        environment.eval_str("(length (cons 1 2))"),
+        Err(EvalError::ArgsMismatch(reason)) if reason.contains("improper list")
+    ));
+    assert!(matches!(
+        environment.eval_str("(reverse (cons 1 2))"),
+        Err(EvalError::ArgsMismatch(reason)) if reason.contains("improper list")
+    ));
+    assert!(environment.eval_str("(length [1] [2])").is_err());
+}
+
+#[test]
+fn nth() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(nth 0 [10 20 30])"), Value::Integer(10));
+    assert_eq!(eval("(nth 2 [10 20 30])"), Value::Integer(30));
+    assert_eq!(eval("(nth 5 [10])"), Value::Nil);
+    assert_eq!(eval("(nth 0 nil)"), Value::Nil);
+    assert_eq!(eval("(elt [10 20 30] 1)"), Value::Integer(20));
+    assert_eq!(eval("(elt [10] 5)"), Value::Nil);
+
+    let mut environment = Environment::new_configured();
+
+    assert!(environment.eval_str("(nth -1 [1])").is_err());
+    assert!(environment.eval_str("(nth 'a [1])").is_err());
+    assert!(environment.eval_str("(nth 0 5)").is_err());
+    assert!(environment.eval_str("(elt [1] -1)").is_err());
+}
+
+#[test]
+fn boolean() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(and)"), Value::T);
+    assert_eq!(eval("(and 1 2 3)"), Value::Integer(3));
+    assert_eq!(eval("(and 1 nil 3)"), Value::Nil);
+    assert_eq!(eval("(or)"), Value::Nil);
+    assert_eq!(eval("(or nil 2 3)"), Value::Integer(2));
+    assert_eq!(eval("(or nil [])"), Value::Nil);
+    assert_eq!(eval("(not nil)"), Value::T);
+    assert_eq!(eval("(not 1)"), Value::Nil);
+
+    // Unreached arguments must not be evaluated.
+    let mut environment = Environment::new_configured();
+
+    environment
+        .eval_str("(and nil (set 'and-ran t) (debug 'and-ran))")
+        .unwrap();
+    environment
+        .eval_str("(or t (set 'or-ran t) (debug 'or-ran))")
+        .unwrap();
+
+    assert!(environment.eval_str("and-ran").is_err());
+    assert!(environment.eval_str("or-ran").is_err());
+
+    assert!(environment.eval_str("(not)").is_err());
+}
+
+#[test]
+fn lambda() {
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(
+        environment.eval_str("((lambda [x] (* x x)) 5)").unwrap(),
+        Value::Integer(25)
+    );
+    assert_eq!(
+        environment.eval_str("((lambda [] 42))").unwrap(),
+        Value::Integer(42)
+    );
+    assert_eq!(
+        environment
+            .eval_str("((lambda [x rest...] (cons x rest)) 1 2 3)")
+            .unwrap(),
+        Value::List(vec![
+            Value::Integer(1),
+            Value::Integer(2),
+            Value::Integer(3)
+        ])
+    );
+
+    environment
+        .eval_str("(set 'square (lambda [x] (* x x)))")
+        .unwrap();
+
+    assert_eq!(
+        environment.eval_str("(square 4)").unwrap(),
+        Value::Integer(16)
+    );
+
+    assert!(environment.eval_str("(lambda)").is_err());
+    assert!(environment.eval_str("(lambda x)").is_err());
+    assert!(environment.eval_str("((lambda [x] x))").is_err());
+    assert!(environment.eval_str("((+ 1 2) 3)").is_err());
+}
+
+#[test]
+fn mapcar() {
+    let mut environment = Environment::new_configured();
+
+    environment.eval_str("(defun square [x] (* x x))").unwrap();
+
+    assert_eq!(
+        environment.eval_str("(mapcar 'square [1 2 3])").unwrap(),
+        Value::List(vec![
+            Value::Integer(1),
+            Value::Integer(4),
+            Value::Integer(9)
+        ])
+    );
+    assert_eq!(
+        environment
+            .eval_str("(mapcar (lambda [x] (+ x 1)) [1 2])")
+            .unwrap(),
+        Value::List(vec![Value::Integer(2), Value::Integer(3)])
+    );
+    assert_eq!(
+        environment.eval_str("(mapcar 'square [])").unwrap(),
+        Value::List(vec![])
+    );
+
+    assert!(environment.eval_str("(mapcar 'car [1 2])").is_err());
+    assert!(environment.eval_str("(mapcar 'undefined [1])").is_err());
+    assert!(environment.eval_str("(mapcar 'square 1)").is_err());
+}
+
+#[test]
+fn display() {
+    let tests = [
+        (Value::Nil, "nil"),
+        (Value::T, "t"),
+        (Value::Integer(-42), "-42"),
+        (Value::Float(3.5), "3.5"),
+        (Value::Float(2.0), "2.0"),
+        (Value::Float(1e300), "1e300"),
+        (Value::Float(1.5e-10), "1.5e-10"),
+        (Value::String("hi".into()), "\"hi\""),
+        (
+            Value::String("\"quoted\"\n\t\\".into()),
+            "\"\\\"quoted\\\"\\n\\t\\\\\"",
+        ),
+        (Value::Symbol(Symbol::from_str("foo")), "foo"),
+        (
+            Value::Symbol(Symbol::new("foo".into(), Quote::Single, false)),
+            "'foo",
+        ),
+        (
+            Value::Symbol(Symbol::new("foo".into(), Quote::Eval, true)),
+            ",foo...",
+        ),
+        (
+            Value::List(vec![Value::Integer(1), Value::List(vec![]), Value::T]),
+            "[1 [] t]",
+        ),
+        (
+            Value::Funcall(
+                Box::new(Value::Symbol(Symbol::from_str("+"))),
+                vec![Value::Integer(1), Value::Integer(2)],
+            ),
+            "(+ 1 2)",
+        ),
+    ];
+
+    for (value, expected) in tests.iter() {
+        assert_eq!(format!("{}", value), *expected);
+        assert_eq!(parse(expected).unwrap(), *value);
+    }
+
+    let lambda = Environment::new_configured()
+        .eval_str("(lambda [x rest...] (car rest) x)")
+        .unwrap();
+
+    assert_eq!(format!("{}", lambda), "(lambda [x rest...] (car rest) x)");
+}
+
+#[test]
+fn error_display() {
+    let tests = [
+        (
+            EvalError::ArgsMismatch("`car': This function takes a list".into()),
+            "Args mismatch: `car': This function takes a list",
+        ),
+        (EvalError::VariableIsVoid("x".into()), "Variable is void: x"),
+        (EvalError::DivisionByZero("/".into()), "Division by zero: /"),
+        (
+            EvalError::ArithmeticOverflow("+".into()),
+            "Arithmetic overflow: +",
+        ),
+        (
+            EvalError::FunctionDefinitionIsVoid("foo".into(), None),
+            "Function definition is void: foo",
+        ),
+        (
+            EvalError::FunctionDefinitionIsVoid("cr".into(), Some("car".into())),
+            "Function definition is void: cr. Did you mean car?",
+        ),
+        (
+            EvalError::StackOverflow("foo".into()),
+            "Stack overflow: foo",
+        ),
+        (
+            EvalError::Thrown {
+                tag: Value::Symbol(Symbol::new("done".into(), Quote::Single, false)),
+                value: Value::Integer(1),
+            },
+            "No catch for tag: 'done, 1",
+        ),
+        (EvalError::UserError("boom".into()), "boom"),
+        (
+            EvalError::FailedToReadFile(
+                "foo.crisp".into(),
+                std::io::Error::new(std::io::ErrorKind::NotFound, "not found"),
+            ),
+            "Failed to read file foo.crisp: not found",
+        ),
+        (
+            EvalError::FailedToWriteFile(
+                "foo.txt".into(),
+                std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied"),
+            ),
+            "Failed to write file foo.txt: denied",
+        ),
+        (
+            EvalError::FailedToWriteOutput(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "broken pipe",
+            )),
+            "Failed to write output: broken pipe",
+        ),
+        (
+            EvalError::FailedToParse(ParserError::new(
+                ParserErrorKind::MalformedInput("oops".into()),
+                0,
+            )),
+            "Failed to parse: Malformed input: oops at 1:1",
+        ),
+        (
+            EvalError::FailedToParse(ParserError::new(ParserErrorKind::IntegerOverflow, 0)),
+            "Failed to parse: Integer literal is too large at 1:1",
+        ),
+        (
+            EvalError::FailedToParse(ParserError::new(
+                ParserErrorKind::InvalidEscapeSequence('q'),
+                0,
+            )),
+            "Failed to parse: Invalid escape sequence: \\q at 1:1",
+        ),
+        (
+            EvalError::FailedToParse(ParserError::new(ParserErrorKind::UnmatchedParentheses, 0)),
+            "Failed to parse: Unmatched parentheses at 1:1",
+        ),
+        (
+            EvalError::FailedToParse(ParserError::new(ParserErrorKind::EmptyFuncall, 0)),
+            "Failed to parse: Empty function call at 1:1",
+        ),
+        (
+            EvalError::FailedToParse(ParserError::new(ParserErrorKind::InvalidFuncall, 0)),
+            "Failed to parse: Invalid function call at 1:1",
+        ),
+        (
+            EvalError::FailedToParse(ParserError::new(ParserErrorKind::NoMatchingParser, 0)),
+            "Failed to parse: No matching parser at 1:1",
+        ),
+        (
+            EvalError::FailedToParse(ParserError::new(ParserErrorKind::MisplacedDot, 0)),
+            "Failed to parse: Misplaced dot at 1:1",
+        ),
+    ];
+
+    for (error, expected) in tests.iter() {
+        assert_eq!(format!("{}", error), *expected);
+    }
+}
+
+#[test]
+fn comments() {
+    assert_eq!(
+        parse("(+ 1 ; ignore me\n 2)").unwrap(),
+        parse("(+ 1 2)").unwrap()
+    );
+    assert_eq!(parse("; just a comment\n42").unwrap(), Value::Integer(42));
+    assert_eq!(parse("\";\"").unwrap(), Value::String(";".into()));
+    assert_eq!(
+        parse("[\"a ; b\" ; c\n]").unwrap(),
+        Value::List(vec![Value::String("a ; b".into())])
+    );
+    assert_eq!(
+        parse("(list ?; 1)").unwrap(),
+        parse("(list ?\\; 1)").unwrap()
+    );
+    assert_eq!(
+        parse("[?\\; 1] ; c").unwrap(),
+        Value::List(vec![Value::Char(';'), Value::Integer(1)])
+    );
+    assert_eq!(
+        parse("[?\" \"a;b\"]").unwrap(),
+        Value::List(vec![Value::Char('"'), Value::String("a;b".into())])
+    );
+    assert_eq!(
+        parse(&Value::Char(';').to_string()).unwrap(),
+        Value::Char(';')
+    );
+    assert_eq!(
+        crate::parsers::parse_all("?; [?\\; ?\"] ; c\n?a").unwrap(),
+        vec![
+            Value::Char(';'),
+            Value::List(vec![Value::Char(';'), Value::Char('"')]),
+            Value::Char('a'),
+        ]
+    );
+
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(
+        environment.eval_file("test/comments.crisp".into()).unwrap(),
+        Value::Integer(42)
+    );
+    assert_eq!(
+        environment.eval_str("greeting").unwrap(),
+        Value::String("hello; world".into())
+    );
+}
+
+#[test]
+fn parser_error_position() {
+    let position = |buffer| {
+        let err = parse(buffer).unwrap_err();
+        (err.kind, err.line, err.column)
+    };
+
+    assert_eq!(
+        position("(+ 1\n   (* 2 3]\n)"),
+        (ParserErrorKind::UnmatchedParentheses, 2, 10)
+    );
+    assert_eq!(
+        position("(progn\n  (+ 1 2)\n  (- 3"),
+        (ParserErrorKind::UnmatchedParentheses, 3, 3)
+    );
+    if cfg!(not(feature = "bignum")) {
+        assert_eq!(
+            position("[1\n 2\n 99999999999999999999]"),
+            (ParserErrorKind::IntegerOverflow, 3, 2)
+        );
+    }
+    assert_eq!(
+        position("; comment\n  \"bad \\q\""),
+        (ParserErrorKind::InvalidEscapeSequence('q'), 2, 9)
+    );
+
+    let mut environment = Environment::new_configured();
+
+    match environment.eval_file("test/unbalanced.crisp".into()) {
+        Err(EvalError::FailedToParseFile(name, err)) => {
+            assert_eq!(name, "test/unbalanced.crisp");
+            assert_eq!(err.kind, ParserErrorKind::UnmatchedParentheses);
+            assert_eq!((err.line, err.column), (4, 15));
+        }
+        _ => panic!("test/unbalanced.crisp parsed without an error"),
+    }
+}
+
+#[test]
+fn integer_radix() {
+    assert_eq!(parse("0x1F").unwrap(), Value::Integer(31));
+    assert_eq!(parse("0xff").unwrap(), Value::Integer(255));
+    assert_eq!(parse("-0xFF").unwrap(), Value::Integer(-255));
+    assert_eq!(parse("0o17").unwrap(), Value::Integer(15));
+    assert_eq!(parse("+0o17").unwrap(), Value::Integer(15));
+    assert_eq!(parse("0b1010").unwrap(), Value::Integer(10));
+    assert_eq!(parse("-0b1").unwrap(), Value::Integer(-1));
+    assert_eq!(
+        parse("-0x8000000000000000").unwrap(),
+        Value::Integer(Integer::MIN)
+    );
+    assert_eq!(parse("0xFFFFFFFFFF").unwrap(), Value::Integer(0xFFFFFFFFFF));
+
+    if cfg!(not(feature = "bignum")) {
+        assert!(parse("0xFFFFFFFFFFFFFFFFFF").is_err());
+        assert!(parse("0x8000000000000000").is_err());
+    }
+
+    // Digits outside of the radix make it a symbol instead.
+    assert!(matches!(parse("0b102"), Ok(Value::Symbol(_))));
+    assert!(matches!(parse("0o8"), Ok(Value::Symbol(_))));
+}
+
+#[test]
+fn bitwise() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(logand 12 10)"), Value::Integer(8));
+    assert_eq!(eval("(logand 0xFF 0x0F 0x3)"), Value::Integer(3));
+    assert_eq!(eval("(logand)"), Value::Integer(-1));
+    assert_eq!(eval("(logior 12 10)"), Value::Integer(14));
+    assert_eq!(eval("(logior)"), Value::Integer(0));
+    assert_eq!(eval("(logxor 12 10)"), Value::Integer(6));
+    assert_eq!(eval("(lognot 0)"), Value::Integer(-1));
+    assert_eq!(eval("(lognot 5)"), Value::Integer(-6));
+    assert_eq!(eval("(ash 1 4)"), Value::Integer(16));
+    assert_eq!(eval("(ash 256 -4)"), Value::Integer(16));
+    assert_eq!(eval("(ash -16 -2)"), Value::Integer(-4));
+    assert_eq!(eval("(ash -1 -100)"), Value::Integer(-1));
+    assert_eq!(eval("(ash 0 100)"), Value::Integer(0));
+
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(
+        environment.eval_str("(ash 1 62)").unwrap(),
+        Value::Integer(4611686018427387904)
+    );
+    assert!(environment.eval_str("(ash 1 63)").is_err());
+    assert!(environment.eval_str("(logand 1 1.5)").is_err());
+    assert!(environment.eval_str("(lognot \"a\")").is_err());
+    assert!(environment.eval_str("(ash 1)").is_err());
+}
+
+#[test]
+fn concat_and_format() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+
+    assert_eq!(
+        eval("(concat \"a\" \"b\" \"c\")"),
+        Value::String("abc".into())
+    );
+    assert_eq!(eval("(concat)"), Value::String("".into()));
+    assert_eq!(
+        eval("(format \"~a + ~a\" 1 2)"),
+        Value::String("1 + 2".into())
+    );
+    assert_eq!(
+        eval("(format \"~a and ~s\" \"raw\" \"quoted\")"),
+        Value::String("raw and \"quoted\"".into())
+    );
+    assert_eq!(
+        eval("(format \"~a ~~ ~a\" [1 2] (+ 1 2))"),
+        Value::String("[1 2] ~ 3".into())
+    );
+
+    let mut environment = Environment::new_configured();
+
+    assert!(environment.eval_str("(concat \"a\" 1)").is_err());
+    assert!(environment.eval_str("(format \"~a ~a\" 1)").is_err());
+    assert!(environment.eval_str("(format \"~a\" 1 2)").is_err());
+    assert!(environment.eval_str("(format \"~q\" 1)").is_err());
+    assert!(environment.eval_str("(format 1)").is_err());
+}
+
+#[test]
+fn type_predicates() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(integerp 1)"), Value::T);
+    assert_eq!(eval("(integerp 1.5)"), Value::Nil);
+    assert_eq!(eval("(stringp \"a\")"), Value::T);
+    assert_eq!(eval("(stringp 'a)"), Value::Nil);
+    assert_eq!(eval("(symbolp 'a)"), Value::T);
+    assert_eq!(eval("(symbolp \"a\")"), Value::Nil);
+    assert_eq!(eval("(listp [1 2])"), Value::T);
+    assert_eq!(eval("(listp nil)"), Value::T);
+    assert_eq!(eval("(listp 1)"), Value::Nil);
+    assert_eq!(eval("(null nil)"), Value::T);
+    assert_eq!(eval("(null [])"), Value::T);
+    assert_eq!(eval("(null [1])"), Value::Nil);
+    assert_eq!(eval("(null \"\")"), Value::T);
+    assert_eq!(eval("(null 0)"), Value::Nil);
+    assert_eq!(eval("(integerp (+ 1 2))"), Value::T);
+
+    assert!(Environment::new_configured()
+        .eval_str("(integerp 1 2)")
+        .is_err());
+}
+
+#[test]
+fn print() {
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(
+        environment.eval_str("(println \"hello\")").unwrap(),
+        Value::String("hello".into())
+    );
+    assert_eq!(
+        environment.eval_str("(+ 1 (print (* 2 3)))").unwrap(),
+        Value::Integer(7)
+    );
+    assert_eq!(
+        environment.eval_str("(println [1 \"two\"])").unwrap(),
+        Value::List(vec![Value::Integer(1), Value::String("two".into())])
+    );
+
+    assert!(environment.eval_str("(println)").is_err());
+}
+
+#[test]
+fn output() {
+    let mut environment = Environment::new_configured();
+    let output = Output::default();
+
+    environment.set_output(Box::new(output.clone()));
+
+    environment.eval_str("(println \"hi\")").unwrap();
+    assert_eq!(output.contents(), "hi\n");
+
+    environment
+        .eval_str("(progn (print 1) (print \" \") (println [?a \"b\"]))")
+        .unwrap();
+    environment.eval_str("(pprint [[1] 2])").unwrap();
+    environment.eval_str("(debug \"x\")").unwrap();
+    assert_eq!(
+        output.contents(),
+        "hi\n1 [?a \"b\"]\n[[1]\n 2]\nString(\"x\")\n"
+    );
+
+    // A buffer with no room left fails every write.
+    environment.set_output(Box::new(io::Cursor::new([0u8; 0])));
+
+    assert!(matches!(
+        environment.eval_str("(println \"hi\")"),
+        Err(EvalError::FailedToWriteOutput(_))
+    ));
+}
+
+#[test]
+fn pprint() {
+    let mut environment = Environment::new_configured();
+
+    let value = environment.eval_str("(pprint [[1 2] [3 [4 5]]])").unwrap();
+
+    assert_eq!(value.pretty(), "[[1 2]\n [3\n  [4 5]]]");
+    assert_eq!(value.to_string(), "[[1 2] [3 [4 5]]]");
+
+    // Flat lists and atoms stay on one line.
+    assert_eq!(parse("[1 [] \"a b\"]").unwrap().pretty(), "[1 [] \"a b\"]");
+    assert_eq!(parse("42").unwrap().pretty(), "42");
+
+    assert_eq!(
+        parse("(defun f [x] (if x [1 2] nil))").unwrap().pretty(),
+        "(defun\n f\n [x]\n (if\n  x\n  [1 2]\n  nil))"
+    );
+    assert_eq!(
+        environment.eval_str("(cons [1] 2)").unwrap().pretty(),
+        "([1]\n . 2)"
+    );
+
+    assert!(environment.eval_str("(pprint)").is_err());
+}
+
+#[test]
+fn read_line() {
+    let mut environment = Environment::new_configured();
+
+    environment.set_input(Box::new(std::io::Cursor::new("first\nsecond\r\n")));
+
+    assert_eq!(
+        environment.eval_str("(read-line)").unwrap(),
+        Value::String("first".into())
+    );
+    assert_eq!(
+        environment.eval_str("(read-line)").unwrap(),
+        Value::String("second".into())
+    );
+    assert_eq!(environment.eval_str("(read-line)").unwrap(), Value::Nil);
+
+    assert!(environment.eval_str("(read-line 1)").is_err());
+}
+
+#[test]
+fn when_unless() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(when t 1 2 3)"), Value::Integer(3));
+    assert_eq!(eval("(when nil 1 2 3)"), Value::Nil);
+    assert_eq!(eval("(when t)"), Value::Nil);
+    assert_eq!(eval("(unless t 1)"), Value::Nil);
+    assert_eq!(eval("(unless nil 1 2)"), Value::Integer(2));
+
+    // The body must not run when the condition doesn't hold.
+    let mut environment = Environment::new_configured();
+
+    environment.eval_str("(when nil (set 'ran t))").unwrap();
+    environment.eval_str("(unless t (set 'ran t))").unwrap();
+
+    assert!(environment.eval_str("ran").is_err());
+    assert!(environment.eval_str("(when)").is_err());
+}
+
+#[test]
+fn incf_decf() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer);
+
+    assert_eq!(
+        eval("(progn (set 'x 5) (incf x) (incf x 3))").unwrap(),
+        Value::Integer(9)
+    );
+    assert_eq!(
+        eval("(progn (set 'x 5) (decf x) (decf x 3) x)").unwrap(),
+        Value::Integer(1)
+    );
+
+    assert!(matches!(
+        eval("(incf void)"),
+        Err(EvalError::VariableIsVoid(_))
+    ));
+    assert!(matches!(
+        eval("(progn (set 'x 9223372036854775807) (incf x))"),
+        Err(EvalError::ArithmeticOverflow(_))
+    ));
+    assert!(eval("(progn (set 'x \"a\") (incf x))").is_err());
+}
+
+#[test]
+fn let_binding() {
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(
+        environment.eval_str("(let 'x 1)").unwrap(),
+        Value::Integer(1)
+    );
+    assert_eq!(environment.eval_str("x").unwrap(), Value::Integer(1));
+
+    // Locals land in the function's own frame, even from inside nested builtins...
+    environment
+        .eval_str("(defun local [] (when t (let 'y 2)) y)")
+        .unwrap();
+    assert_eq!(environment.eval_str("(local)").unwrap(), Value::Integer(2));
+
+    // ...and don't leak to the caller.
+    assert!(environment.eval_str("y").is_err());
+
+    environment
+        .eval_str("(defun shadow [] (let 'x 3) x)")
+        .unwrap();
+    assert_eq!(environment.eval_str("(shadow)").unwrap(), Value::Integer(3));
+    assert_eq!(environment.eval_str("x").unwrap(), Value::Integer(1));
+}
+
+#[test]
+fn let_at_top_level() {
+    let mut environment = Environment::new_configured();
+
+    // Only the top level is on the stack, so there's no frame below it.
+    assert!(environment.outer().is_none());
+
+    // Calling the builtin without pushing a frame for it binds at the top level.
+    let let_ = environment.function(&Symbol::from_str("let")).unwrap();
+    let args = vec![
+        Value::Symbol(Symbol::new("x".into(), Quote::Single, false)),
+        Value::Integer(1),
+    ];
+
+    assert_eq!(
+        let_.call(&mut environment, args).unwrap(),
+        Value::Integer(1)
+    );
+    assert_eq!(environment.eval_str("x").unwrap(), Value::Integer(1));
+    assert_eq!(
+        environment.eval_str("(progn (let 'y 2) (+ x y))").unwrap(),
+        Value::Integer(3)
+    );
+    assert_eq!(environment.eval_str("y").unwrap(), Value::Integer(2));
+}
+
+#[test]
+fn block() {
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(
+        environment
+            .eval_str("(block (let 'x 1) (let 'y (+ x 1)) (* x y))")
+            .unwrap(),
+        Value::Integer(2)
+    );
+    assert!(matches!(
+        environment.eval_str("x"),
+        Err(EvalError::VariableIsVoid(_))
+    ));
+    assert_eq!(environment.eval_str("(block)").unwrap(), Value::Nil);
+
+    // Blocks see the variables around them, and `set` still reaches them.
+    environment
+        .eval_str("(defun counter [] (let 'n 0) (block (let 'step 2) (set 'n (+ n step))) n)")
+        .unwrap();
+    assert_eq!(
+        environment.eval_str("(counter)").unwrap(),
+        Value::Integer(2)
+    );
+    assert!(environment.eval_str("step").is_err());
+
+    // Lambdas made inside keep the block's bindings.
+    assert_eq!(
+        environment
+            .eval_str("(funcall (block (let 'z 5) (lambda [] z)))")
+            .unwrap(),
+        Value::Integer(5)
+    );
+}
+
+#[test]
+fn let_star() {
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(
+        environment
+            .eval_str("(let* [[a 1] [b (+ a 1)]] (list a b))")
+            .unwrap(),
+        Value::List(vec![Value::Integer(1), Value::Integer(2)])
+    );
+    assert_eq!(
+        environment.eval_str("(let* [] 1 2)").unwrap(),
+        Value::Integer(2)
+    );
+
+    assert!(environment.eval_str("a").is_err());
+    assert!(environment.eval_str("b").is_err());
+
+    assert!(environment.eval_str("(let* [[a]] a)").is_err());
+    assert!(environment.eval_str("(let* [[1 2]] 1)").is_err());
+    assert!(environment.eval_str("(let* [a 1] a)").is_err());
+    assert!(environment.eval_str("(let*)").is_err());
+}
+
+/// Run `test` on a thread with room for recursing as deep as the interpreter allows, like `main`
+/// does. The default test thread stack doesn't have it in debug builds.
+fn with_large_stack<F>(test: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(test)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn stack_overflow() {
+    with_large_stack(stack_overflow_on_large_stack);
+}
+
+fn stack_overflow_on_large_stack() {
+    let mut environment = Environment::new_configured();
+
+    environment
+        .eval_str("(defun forever [n] (when t (forever (+ n 1))))")
+        .unwrap();
+
+    assert!(matches!(
+        environment.eval_str("(forever 0)"),
+        Err(EvalError::StackOverflow(_))
+    ));
+
+    // The stack unwinds properly, so the environment stays usable.
+    assert_eq!(environment.eval_str("(+ 1 2)").unwrap(), Value::Integer(3));
+
+    environment.set_max_depth(20);
+    environment
+        .eval_str("(defun depth [n] (if (= n 0) 0 (+ 1 (depth (- n 1)))))")
+        .unwrap();
+
+    assert!(environment.eval_str("(depth 2)").is_ok());
+    assert!(matches!(
+        environment.eval_str("(depth 20)"),
+        Err(EvalError::StackOverflow(_))
+    ));
+}
+
+#[test]
+fn deep_recursion() {
+    with_large_stack(|| {
+        let mut environment = Environment::new_configured();
+
+        // Only the calls to `cnt` count towards the limit, not the builtins around them.
+        environment
+            .eval_str("(defun cnt [n] (if (= n 0) 0 (+ 1 (cnt (- n 1)))))")
+            .unwrap();
+        assert_eq!(
+            environment.eval_str("(cnt 1000)").unwrap(),
+            Value::Integer(1000)
+        );
+
+        environment.set_max_depth(10);
+        assert_eq!(environment.eval_str("(cnt 9)").unwrap(), Value::Integer(9));
+        assert!(matches!(
+            environment.eval_str("(cnt 10)"),
+            Err(EvalError::StackOverflow(_))
+        ));
+    });
+}
+
+#[test]
+fn tail_calls() {
+    let mut environment = Environment::new_configured();
+
+    environment
+        .eval_str("(defun countdown [n] (if (= n 0) 'done (countdown (- n 1))))")
+        .unwrap();
+    assert_eq!(
+        environment.eval_str("(countdown 100000)").unwrap(),
+        Value::Symbol(Symbol::new("done".into(), Quote::Single, false))
+    );
+
+    // Parameters are rebound on every iteration.
+    environment
+        .eval_str("(defun sum [acc n] (if (= n 0) acc (progn (sum (+ acc n) (- n 1)))))")
+        .unwrap();
+    assert_eq!(
+        environment.eval_str("(sum 0 10000)").unwrap(),
+        Value::Integer(50005000)
+    );
+
+    // The new args are all evaluated before any parameter is rebound.
+    environment
+        .eval_str("(defun swap [n a b] (if (= n 0) [a b] (swap (- n 1) b a)))")
+        .unwrap();
+    assert_eq!(
+        environment.eval_str("(swap 1 1 2)").unwrap(),
+        Value::List(vec![Value::Integer(2), Value::Integer(1)])
+    );
+
+    // Calls outside tail position still recurse normally.
+    environment
+        .eval_str("(defun fact [n] (if (= n 0) 1 (* n (fact (- n 1)))))")
+        .unwrap();
+    assert_eq!(
+        environment.eval_str("(fact 10)").unwrap(),
+        Value::Integer(3628800)
+    );
+}
+
+#[test]
+fn quote() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+
+    let sum = parse("(+ 1 2)").unwrap();
+
+    assert_eq!(eval("(quote (+ 1 2))"), sum);
+    assert_eq!(eval("'(+ 1 2)"), sum);
+    assert_eq!(
+        eval("(quote [1 (+ 1 2)])"),
+        Value::List(vec![Value::Integer(1), sum.clone()])
+    );
+    assert_eq!(
+        eval("'[x [(+ 1 2)]]"),
+        Value::List(vec![
+            Value::Symbol(Symbol::from_str("x")),
+            Value::List(vec![sum.clone()])
+        ])
+    );
+
+    // Quoted data survives being passed around as an argument.
+    assert_eq!(
+        eval("(mapcar (lambda [x] x) '[(+ 1 2) y])"),
+        Value::List(vec![sum, Value::Symbol(Symbol::from_str("y"))])
+    );
+
+    assert!(Environment::new_configured()
+        .eval_str("(quote 1 2)")
+        .is_err());
+    assert!(parse("'(+ 1 2").is_err());
+}
+
+#[test]
+fn quasiquote() {
+    let mut environment = Environment::new_configured();
+
+    let mut eval = |buffer| environment.eval_str(buffer).unwrap();
+    let integers =
+        |integers: Vec<Integer>| Value::List(integers.into_iter().map(Value::Integer).collect());
+
+    assert_eq!(eval("`(1 ,(+ 1 1) 3)"), integers(vec![1, 2, 3]));
+    assert_eq!(eval("`[1 2]"), integers(vec![1, 2]));
+    assert_eq!(eval("`()"), integers(vec![]));
+
+    eval("(set 'x 5)");
+
+    // Forms that can be calls stay calls, so templates can build code.
+    assert_eq!(eval("`(x ,x)").to_string(), "(x 5)");
+    assert_eq!(
+        eval("`(1 (2 ,x) [3 (4 ,(+ x 1))])").to_string(),
+        "[1 [2 5] [3 [4 6]]]"
+    );
+    assert_eq!(eval("`((+ 1 ,x) 'y)").to_string(), "((+ 1 5) 'y)");
+
+    assert!(environment.eval_str("`(,y)").is_err());
+    assert!(environment.eval_str("(quasiquote)").is_err());
+}
+
+#[test]
+fn eval() {
+    let mut environment = Environment::new_configured();
+
+    let mut eval = |buffer| environment.eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(eval (quote (+ 1 2)))"), Value::Integer(3));
+    assert_eq!(eval("(eval '(* 2 (+ 1 2)))"), Value::Integer(6));
+    assert_eq!(eval("(eval (list '+ 1 2))"), Value::Integer(3));
+    assert_eq!(eval("(eval (cons '* [6 7]))"), Value::Integer(42));
+    assert_eq!(eval("(eval 5)"), Value::Integer(5));
+
+    eval("(set 'code '(set 'x 10))");
+    eval("(eval code)");
+
+    assert_eq!(eval("x"), Value::Integer(10));
+
+    assert!(environment.eval_str("(eval)").is_err());
+    assert!(environment.eval_str("(eval '(undefined))").is_err());
+}
+
+#[test]
+fn apply() {
+    let mut environment = Environment::new_configured();
+
+    environment
+        .eval_str("(defun add3 [x y z] (+ x y z))")
+        .unwrap();
+
+    let mut eval = |buffer| environment.eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(apply '+ [1 2 3])"), Value::Integer(6));
+    assert_eq!(eval("(apply 'list nil)"), Value::List(vec![]));
+    assert_eq!(eval("(apply 'add3 (list 1 2 3))"), Value::Integer(6));
+    assert_eq!(
+        eval("(apply (lambda [x] (* x 2)) [21])"),
+        Value::Integer(42)
+    );
+    assert_eq!(
+        eval("(apply 'list '[(+ 1 2)])"),
+        parse("[(+ 1 2)]").unwrap()
+    );
+
+    assert!(environment.eval_str("(apply '+ 1)").is_err());
+    assert!(environment.eval_str("(apply 1 [1])").is_err());
+}
+
+#[test]
+fn funcall_builtin() {
+    let mut environment = Environment::new_configured();
+
+    environment.eval_str("(defun twice [x] (* x 2))").unwrap();
+    environment
+        .eval_str("(set 'double (lambda [x] (* x 2)))")
+        .unwrap();
+
+    let mut eval = |buffer| environment.eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(funcall '+ 1 2 3)"), Value::Integer(6));
+    assert_eq!(eval("(funcall 'twice (+ 1 2))"), Value::Integer(6));
+    assert_eq!(
+        eval("(funcall (lambda [x] (* x 2)) 21)"),
+        Value::Integer(42)
+    );
+    assert_eq!(eval("(funcall double 4)"), Value::Integer(8));
+
+    assert!(environment.eval_str("(funcall)").is_err());
+    assert!(environment.eval_str("(funcall 1 2)").is_err());
+}
+
+#[test]
+fn list_manipulation() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer);
+    let integers =
+        |integers: Vec<Integer>| Value::List(integers.into_iter().map(Value::Integer).collect());
+
+    assert_eq!(eval("(reverse [1 2 3])").unwrap(), integers(vec![3, 2, 1]));
+    assert_eq!(eval("(reverse nil)").unwrap(), integers(vec![]));
+    assert_eq!(
+        eval("(append [1 2] [3] [])").unwrap(),
+        integers(vec![1, 2, 3])
+    );
+    assert_eq!(eval("(append)").unwrap(), integers(vec![]));
+    assert_eq!(eval("(last [1 2 3])").unwrap(), Value::Integer(3));
+    assert_eq!(eval("(last [])").unwrap(), Value::Nil);
+
+    assert!(eval("(reverse 1)").is_err());
+    assert!(eval("(append [1] 2)").is_err());
+    assert!(eval("(last \"abc\")").is_err());
+}
+
+#[test]
+fn member_assoc() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer);
+
+    assert_eq!(eval("(member 2 [1 2 3])").unwrap().to_string(), "[2 3]");
+    assert_eq!(eval("(member 'b ['a 'b])").unwrap().to_string(), "['b]");
+    assert_eq!(eval("(member 4 [1 2 3])").unwrap(), Value::Nil);
+    assert_eq!(eval("(member 1 nil)").unwrap(), Value::Nil);
+    assert_eq!(eval("(member 1.0 [1 2])").unwrap().to_string(), "[1 2]");
+
+    assert_eq!(
+        eval("(assoc 'b [['a 1] ['b 2]])").unwrap().to_string(),
+        "['b 2]"
+    );
+    assert_eq!(
+        eval("(assoc \"x\" [[\"x\" 1] [\"x\" 2]])")
+            .unwrap()
+            .to_string(),
+        "[\"x\" 1]"
+    );
+    assert_eq!(eval("(assoc 'c [['a 1] ['b 2]])").unwrap(), Value::Nil);
+    assert_eq!(
+        eval("(assoc 2.0 [[1 'a] [2 'b]])").unwrap().to_string(),
+        "[2 'b]"
+    );
+
+    assert!(eval("(member 1 2)").is_err());
+    assert!(eval("(assoc 'a)").is_err());
+}
+
+#[test]
+fn position_find() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer);
+
+    assert_eq!(eval("(position 2 [1 2 3])").unwrap(), Value::Integer(1));
+    assert_eq!(eval("(position 2 [2 2])").unwrap(), Value::Integer(0));
+    assert_eq!(eval("(position 2.0 [1 2 3])").unwrap(), Value::Integer(1));
+    assert_eq!(eval("(position 4 [1 2 3])").unwrap(), Value::Nil);
+    assert_eq!(eval("(position 1 nil)").unwrap(), Value::Nil);
+    assert_eq!(eval("(position ?l \"hello\")").unwrap(), Value::Integer(2));
+    assert_eq!(eval("(position ?z \"hello\")").unwrap(), Value::Nil);
+
+    assert_eq!(
+        eval("(find \"b\" [\"a\" \"b\"])").unwrap(),
+        Value::String("b".into())
+    );
+    assert_eq!(eval("(find 1 [1.0 1])").unwrap(), Value::Float(1.0));
+    assert_eq!(eval("(find 'c ['a 'b])").unwrap(), Value::Nil);
+    assert_eq!(eval("(find ?e \"hello\")").unwrap(), Value::Char('e'));
+
+    assert!(eval("(position 1 2)").is_err());
+    assert!(eval("(find 1)").is_err());
+}
+
+#[test]
+fn sort() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer);
+
+    assert_eq!(eval("(sort [3 1 2])").unwrap().to_string(), "[1 2 3]");
+    assert_eq!(eval("(sort [2.5 1 -3])").unwrap().to_string(), "[-3 1 2.5]");
+    assert_eq!(eval("(sort nil)").unwrap(), Value::List(vec![]));
+    assert_eq!(
+        eval("(sort [3 1 4 1 5 9 2 6] '>)").unwrap().to_string(),
+        "[9 6 5 4 3 2 1 1]"
+    );
+
+    // Equal elements keep their order.
+    assert_eq!(
+        eval("(sort [[1 'a] [0 'b] [1 'c] [0 'd]] (lambda [x y] (< (car x) (car y))))")
+            .unwrap()
+            .to_string(),
+        "[[0 'b] [0 'd] [1 'a] [1 'c]]"
+    );
+
+    assert!(eval("(sort [1 'a])").is_err());
+    assert!(eval("(sort [1 2] 'undefined)").is_err());
+    assert!(eval("(sort 1)").is_err());
+}
+
+#[test]
+fn reduce() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer);
+
+    assert_eq!(eval("(reduce '+ 0 [1 2 3 4])").unwrap(), Value::Integer(10));
+    assert_eq!(eval("(reduce '+ 5 [])").unwrap(), Value::Integer(5));
+    assert_eq!(
+        eval("(reduce (lambda [acc x] (cons x acc)) nil [1 2 3])")
+            .unwrap()
+            .to_string(),
+        "[3 2 1]"
+    );
+    assert_eq!(
+        eval("(reduce (lambda [acc x] (- acc x)) 10 [1 2])").unwrap(),
+        Value::Integer(7)
+    );
+
+    assert!(eval("(reduce '+ 0 1)").is_err());
+    assert!(eval("(reduce '+ [1 2])").is_err());
+}
+
+#[test]
+fn filter() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer);
+
+    assert_eq!(
+        eval("(filter 'integerp ['a 1 'b 2])").unwrap().to_string(),
+        "[1 2]"
+    );
+    assert_eq!(
+        eval("(filter (lambda [x] (> x 2)) [5 1 4 2 3])")
+            .unwrap()
+            .to_string(),
+        "[5 4 3]"
+    );
+    assert_eq!(eval("(filter 'integerp nil)").unwrap(), Value::List(vec![]));
+
+    assert!(eval("(filter (lambda [x] (> x 2)) [1 'a])").is_err());
+    assert!(eval("(filter 'integerp 1)").is_err());
+}
+
+/// Feeds the REPL predefined lines.
+struct ScriptedEditor {
+    lines: VecDeque<String>,
+    history: Vec<String>,
+}
+
+impl ScriptedEditor {
+    fn new(lines: &[&str]) -> Self {
+        Self {
+            lines: lines.iter().map(|line| line.to_string()).collect(),
+            history: Vec::new(),
+        }
+    }
+}
+
+impl LineEditor for ScriptedEditor {
+    fn read_line(&mut self, _prompt: &str) -> io::Result<Option<String>> {
+        Ok(self.lines.pop_front())
+    }
+
+    fn add_history(&mut self, line: &str) {
+        self.history.push(line.to_string());
+    }
+}
+
+#[test]
+fn repl_history() {
+    let mut repl = Repl::new(Environment::new_configured());
+    let mut editor = ScriptedEditor::new(&["(set 'x 1)", "", "(+ x 1))", "quit", "(set 'x 2)"]);
+
+    repl.run(&mut editor).unwrap();
+
+    assert_eq!(editor.history, vec!["(set 'x 1)", "(+ x 1))", "quit"]);
+    assert_eq!(editor.lines, vec!["(set 'x 2)"]);
+    assert_eq!(repl.environment.eval_str("x").unwrap(), Value::Integer(1));
+
+    // The end of input stops the REPL too.
+    let mut editor = ScriptedEditor::new(&["(+ 1 2)"]);
+
+    repl.run(&mut editor).unwrap();
+
+    assert!(editor.lines.is_empty());
+}
+
+#[test]
+fn multiline_input() {
+    use crate::parsers::is_incomplete;
+
+    assert!(is_incomplete("(+ 1"));
+    assert!(is_incomplete("[1 [2]"));
+    assert!(is_incomplete("(concat \"a"));
+    assert!(is_incomplete("(+ 1 ; )"));
+    assert!(!is_incomplete("(+ 1 2)"));
+    assert!(!is_incomplete("(concat \"(\")"));
+    assert!(!is_incomplete("(list ?( ?\\))"));
+    assert!(!is_incomplete("(list ?; 1)"));
+    assert!(!is_incomplete("(list ?\" \"a;b\")"));
+    assert!(!is_incomplete("(+ 1))"));
+    assert!(!is_incomplete("(+ 1]"));
+    assert!(!is_incomplete("x"));
+
+    let mut editor = ScriptedEditor::new(&["(+ 1", "  2", "  3)", "x"]);
+
+    assert_eq!(
+        crate::repl::read_form(&mut editor).unwrap().unwrap(),
+        "(+ 1\n  2\n  3)"
+    );
+    assert_eq!(crate::repl::read_form(&mut editor).unwrap().unwrap(), "x");
+    assert_eq!(crate::repl::read_form(&mut editor).unwrap(), None);
+
+    // A form cut off by the end of input is still evaluated and fails to parse.
+    let mut editor = ScriptedEditor::new(&["(+ 1"]);
+
+    assert_eq!(
+        crate::repl::read_form(&mut editor).unwrap().unwrap(),
+        "(+ 1"
+    );
+
+    let mut repl = Repl::new(Environment::new_configured());
+    let mut editor = ScriptedEditor::new(&["(set 'x", "5)"]);
+
+    repl.run(&mut editor).unwrap();
+
+    assert_eq!(repl.environment.eval_str("x").unwrap(), Value::Integer(5));
+    assert_eq!(editor.history, vec!["(set 'x\n5)"]);
+}
+
+#[test]
+fn repl_commands() {
+    let mut repl = Repl::new(Environment::new_configured());
+
+    assert_eq!(repl.command(":load test/comments.crisp"), "42");
+    assert_eq!(
+        repl.environment.eval_str("(twice 1)").unwrap(),
+        Value::Integer(2)
+    );
+    assert!(repl
+        .command(":load test/missing.crisp")
+        .starts_with("Failed to read file"));
+    assert!(repl.command(":load").starts_with("Usage"));
+
+    repl.environment.eval_str("(set 'x 1)").unwrap();
+
+    let env = repl.command(":env");
+
+    assert!(env.contains("  x = 1"));
+    assert!(env.contains(" twice "));
+    assert!(env.contains(" mapcar "));
+
+    assert!(repl.command(":help").contains(":load"));
+    assert_eq!(
+        repl.command(":frobnicate"),
+        "Unknown command: :frobnicate. Try :help"
+    );
+}
+
+#[test]
+fn repl_timing() {
+    let mut repl = Repl::new(Environment::new_configured());
+
+    assert_eq!(repl.command(":time"), "Timing is off");
+    assert_eq!(repl.eval("(+ 1 2)"), "3");
+
+    assert_eq!(repl.command(":time on"), "Timing is on");
+    assert_eq!(repl.command(":time"), "Timing is on");
+    assert!(repl.eval("(+ 1 2)").starts_with("3\n; evaluated in "));
+    assert!(repl
+        .eval("void")
+        .starts_with("Variable is void: void\n; evaluated in "));
+
+    assert_eq!(repl.command(":time maybe"), "Usage: :time [on|off]");
+    assert_eq!(repl.command(":time"), "Timing is on");
+
+    assert_eq!(repl.command(":time off"), "Timing is off");
+    assert_eq!(repl.eval("(+ 1 2)"), "3");
+}
+
+#[test]
+fn repl_several_forms() {
+    let mut repl = Repl::new(Environment::new_configured());
+
+    assert_eq!(repl.eval("(set 'x 1) (+ x 1) \"s\""), "1\n2\n\"s\"");
+    assert_eq!(repl.eval("1 missing 2"), "Variable is void: missing");
+}
+
+#[test]
+fn eval_flag() {
+    use crate::Task;
+
+    let args = |args: &[&str]| crate::parse_args(args.iter().map(|arg| arg.to_string()).collect());
+
+    assert_eq!(
+        args(&["-e", "(+ 1 2)", "file.crisp", "-", "--eval", "x"]).unwrap(),
+        vec![
+            Task::Eval("(+ 1 2)".into()),
+            Task::File("file.crisp".into()),
+            Task::Stdin,
+            Task::Eval("x".into()),
+        ]
+    );
+    assert!(args(&[]).unwrap().is_empty());
+    assert!(args(&["-e"]).is_err());
+
+    let run = |environment: &mut Environment, tasks| {
+        let mut output: Vec<u8> = Vec::new();
+        crate::run_tasks(environment, tasks, &mut output)
+            .map(|_| String::from_utf8(output).unwrap())
+    };
+
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(
+        run(&mut environment, args(&["-e", "(+ 1 2)"]).unwrap()).unwrap(),
+        "3\n"
+    );
+
+    // Expressions share one environment.
+    assert_eq!(
+        run(
+            &mut environment,
+            args(&["-e", "(set 'x 2)", "-e", "(* x 21)"]).unwrap()
+        )
+        .unwrap(),
+        "2\n42\n"
+    );
+
+    assert!(run(&mut environment, args(&["-e", "(+ 1"]).unwrap()).is_err());
+}
+
+#[test]
+fn command_line_flags() {
+    use crate::{RuntimeError, Task};
+
+    let args = |args: &[&str]| crate::parse_args(args.iter().map(|arg| arg.to_string()).collect());
+
+    assert_eq!(args(&["--help"]).unwrap(), vec![Task::Help]);
+    assert_eq!(args(&["a.crisp", "-h", "-e"]).unwrap(), vec![Task::Help]);
+    assert_eq!(args(&["-V"]).unwrap(), vec![Task::Version]);
+    assert_eq!(args(&["--version"]).unwrap(), vec![Task::Version]);
+
+    match args(&["--frobnicate"]) {
+        Err(RuntimeError::Usage(reason)) => {
+            assert_eq!(reason, "Unknown flag: --frobnicate. Try --help")
+        }
+        result => panic!("Expected a usage error, got {:?}", result),
+    }
+
+    let output = |task| {
+        let mut output: Vec<u8> = Vec::new();
+        crate::run_tasks(&mut Environment::new(), vec![task], &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    assert!(output(Task::Help).starts_with("Usage: crisp"));
+    assert!(output(Task::Help).contains("--eval"));
+    assert_eq!(
+        output(Task::Version),
+        format!("crisp {}\n", env!("CARGO_PKG_VERSION"))
+    );
+}
+
+#[test]
+fn check_flag() {
+    use crate::Task;
+
+    let args = |args: &[&str]| crate::parse_args(args.iter().map(|arg| arg.to_string()).collect());
+
+    assert_eq!(
+        args(&["--check", "a.crisp"]).unwrap(),
+        vec![Task::Check("a.crisp".into())]
+    );
+    assert!(args(&["--check"]).is_err());
+
+    let check = |file: &str| {
+        let mut environment = Environment::new_configured();
+        let mut output: Vec<u8> = Vec::new();
+        let result = crate::run_tasks(
+            &mut environment,
+            vec![Task::Check(file.into())],
+            &mut output,
+        );
+
+        assert!(output.is_empty());
+        // Nothing in the file gets evaluated.
+        assert!(environment.eval_str("(fibonacci 10)").is_err());
+
+        result.map_err(|error| error.to_string())
+    };
+
+    assert!(check("test/fibonacci.crisp").is_ok());
+    assert_eq!(
+        check("test/unbalanced.crisp"),
+        Err("Failed to parse test/unbalanced.crisp: Unmatched parentheses at 4:15".into())
+    );
+    assert!(check("test/missing.crisp")
+        .unwrap_err()
+        .starts_with("Failed to read file test/missing.crisp"));
+}
+
+#[test]
+fn register() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let mut environment = Environment::new_configured();
+    let counter = Rc::new(Cell::new(0));
+
+    {
+        let counter = counter.clone();
+
+        environment.register("count", move |environment, args| {
+            let mut step = 1;
+
+            if let Some(arg) = args.first() {
+                match arg.eval(environment)? {
+                    Value::Integer(i) => step = i,
+                    _ => return Err(EvalError::ArgsMismatch("count: Expected an integer".into())),
+                }
+            }
+
+            counter.set(counter.get() + step);
+            Ok(Value::Integer(counter.get()))
+        });
+    }
+
+    assert_eq!(environment.eval_str("(count)").unwrap(), Value::Integer(1));
+    assert_eq!(
+        environment.eval_str("(count (+ 1 2))").unwrap(),
+        Value::Integer(4)
+    );
+    assert_eq!(
+        environment.eval_str("(mapcar 'count [1 1])").unwrap(),
+        Value::List(vec![Value::Integer(5), Value::Integer(6)])
+    );
+    assert!(environment.eval_str("(count 'a)").is_err());
+
+    assert_eq!(counter.get(), 6);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip() {
+    let round_trip = |value: Value| {
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), value);
+        json
+    };
+
+    assert_eq!(round_trip(Value::Nil), "\"Nil\"");
+    assert_eq!(round_trip(Value::T), "\"T\"");
+    assert_eq!(round_trip(Value::Integer(-5)), "{\"Integer\":-5}");
+    assert_eq!(round_trip(Value::Float(2.5)), "{\"Float\":2.5}");
+    assert_eq!(
+        round_trip(Value::String("a \"b\"".into())),
+        "{\"String\":\"a \\\"b\\\"\"}"
+    );
+
+    round_trip(parse("'symbol").unwrap());
+    round_trip(parse(",rest...").unwrap());
+    round_trip(parse("(+ 1 (* 2 3))").unwrap());
+    round_trip(parse("[1 [\"a\" [nil t]] 'b (f)]").unwrap());
+    round_trip(
+        Environment::new_configured()
+            .eval_str("(lambda [x 'y] (+ x y))")
+            .unwrap(),
+    );
+
+    // Hash maps become a list of key/value pairs.
+    let table = Environment::new_configured()
+        .eval_str("(make-hash 1 [2 3])")
+        .unwrap();
+    assert_eq!(
+        round_trip(table),
+        "{\"HashMap\":[[{\"Integer\":1},{\"List\":[{\"Integer\":2},{\"Integer\":3}]}]]}"
+    );
+    round_trip(
+        Environment::new_configured()
+            .eval_str("(make-hash 'a 1 \"two\" 2 ?c nil)")
+            .unwrap(),
+    );
+    assert!(serde_json::from_str::<Value>("{\"HashMap\":[[{\"Float\":1.5},\"T\"]]}").is_err());
+
+    #[cfg(feature = "bignum")]
+    assert_eq!(
+        round_trip(parse("-100000000000000000000").unwrap()),
+        "{\"BigInt\":\"-100000000000000000000\"}"
+    );
+}
+
+#[test]
+fn to_string_read() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer);
+
+    assert_eq!(
+        eval("(to-string [1 2])").unwrap(),
+        Value::String("[1 2]".into())
+    );
+    assert_eq!(
+        eval("(to-string \"a\")").unwrap(),
+        Value::String("\"a\"".into())
+    );
+    assert_eq!(
+        eval("(concat \"x = \" (to-string 2.5))").unwrap(),
+        Value::String("x = 2.5".into())
+    );
+
+    assert_eq!(
+        eval("(read \"(+ 1 2)\")").unwrap(),
+        parse("(+ 1 2)").unwrap()
+    );
+    assert_eq!(
+        eval("(eval (read \"(+ 1 2)\"))").unwrap(),
+        Value::Integer(3)
+    );
+    assert_eq!(
+        eval("(read (to-string '[a \"b\" 1.5]))").unwrap(),
+        parse("[a \"b\" 1.5]").unwrap()
+    );
+
+    assert!(matches!(
+        eval("(read \"(+ 1\")"),
+        Err(EvalError::FailedToParse(_))
+    ));
+    assert!(eval("(read 1)").is_err());
+}
+
+#[test]
+fn string_manipulation() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer);
+    let string = |string: &str| Value::String(string.into());
+
+    assert_eq!(eval("(substring \"hello\" 1 3)").unwrap(), string("el"));
+    assert_eq!(eval("(substring \"hello\" 2)").unwrap(), string("llo"));
+    assert_eq!(eval("(substring \"hello\" 5)").unwrap(), string(""));
+    assert_eq!(eval("(substring \"héllo\" 1 2)").unwrap(), string("é"));
+    assert_eq!(eval("(upcase \"Straße\")").unwrap(), string("STRASSE"));
+    assert_eq!(eval("(downcase \"ÀB\")").unwrap(), string("àb"));
+    assert_eq!(
+        eval("(split \"a,b,c\" \",\")").unwrap().to_string(),
+        "[\"a\" \"b\" \"c\"]"
+    );
+    assert_eq!(
+        eval("(split \"a::b\" \"::\")").unwrap().to_string(),
+        "[\"a\" \"b\"]"
+    );
+
+    assert!(eval("(substring \"hello\" 2 6)").is_err());
+    assert!(eval("(substring \"hello\" 3 2)").is_err());
+    assert!(eval("(substring \"hello\" -1)").is_err());
+    assert!(eval("(substring 'hello 1)").is_err());
+    assert!(eval("(upcase 1)").is_err());
+    assert!(eval("(split \"abc\" \"\")").is_err());
+}
+
+#[test]
+fn characters() {
+    assert_eq!(parse("?a").unwrap(), Value::Char('a'));
+    assert_eq!(parse("?é").unwrap(), Value::Char('é'));
+    assert_eq!(parse("?\\n").unwrap(), Value::Char('\n'));
+    assert_eq!(parse("?\\s").unwrap(), Value::Char(' '));
+    assert_eq!(parse("?\\\\").unwrap(), Value::Char('\\'));
+    assert_eq!(parse("?\\(").unwrap(), Value::Char('('));
+    assert_eq!(
+        parse("[?a ?b]").unwrap(),
+        Value::List(vec![Value::Char('a'), Value::Char('b')])
+    );
+    assert_eq!(
+        parse("?\\q").unwrap_err().kind,
+        ParserErrorKind::InvalidEscapeSequence('q')
+    );
+
+    for source in ["?a", "?\\n", "?\\t", "?\\s", "?\\\\", "?\\]", "?\\\""].iter() {
+        assert_eq!(parse(source).unwrap().to_string(), *source);
+    }
+
+    let eval = |buffer| Environment::new_configured().eval_str(buffer);
+
+    assert_eq!(eval("(char-at \"hello\" 0)").unwrap(), Value::Char('h'));
+    assert_eq!(eval("(char-at \"héllo\" 1)").unwrap(), Value::Char('é'));
+    assert_eq!(eval("(char-code ?a)").unwrap(), Value::Integer(97));
+    assert_eq!(eval("(code-char 955)").unwrap(), Value::Char('λ'));
+    assert_eq!(eval("(= (char-at \"abc\" 2) ?c)").unwrap(), Value::T);
+    assert_eq!(
+        eval("(format \"~a~s\" ?x ?y)").unwrap(),
+        Value::String("x?y".into())
+    );
+
+    assert!(eval("(char-at \"hello\" 5)").is_err());
+    assert!(eval("(char-code \"a\")").is_err());
+    assert!(eval("(code-char -1)").is_err());
+    assert!(eval("(code-char 55296)").is_err());
+}
+
+#[test]
+fn make_string() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer);
+
+    assert_eq!(
+        eval("(make-string 3 ?x)").unwrap(),
+        Value::String("xxx".into())
+    );
+    assert_eq!(
+        eval("(make-string 0 ?x)").unwrap(),
+        Value::String("".into())
+    );
+    assert_eq!(
+        eval("(make-string 2 955)").unwrap(),
+        Value::String("λλ".into())
+    );
+    assert_eq!(
+        eval("(string ?a 98 (char-at \"xcx\" 1))").unwrap(),
+        Value::String("abc".into())
+    );
+    assert_eq!(eval("(string)").unwrap(), Value::String("".into()));
+
+    assert!(eval("(make-string -1 ?x)").is_err());
+    assert!(matches!(
+        eval("(make-string 9223372036854775807 ?€)"),
+        Err(EvalError::ArithmeticOverflow(_))
+    ));
+    assert!(eval("(make-string 3 \"x\")").is_err());
+    assert!(eval("(make-string 3)").is_err());
+    assert!(eval("(string \"a\")").is_err());
+    assert!(eval("(string -1)").is_err());
+}
+
+#[test]
+fn cdr_evaluates_once() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(cdr ['a (+ 1 1) 3])").to_string(), "[2 3]");
+    assert_eq!(eval("(cdr '[a b])").to_string(), "[b]");
+    assert_eq!(eval("(cdr (list 1 '(+ 1 1)))").to_string(), "[(+ 1 1)]");
+    assert_eq!(eval("(cdr (cdr (list 1 'x 'y)))").to_string(), "['y]");
+}
+
+#[test]
+fn car_evaluates_once() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(car [(+ 1 1) 2])"), Value::Integer(2));
+    assert_eq!(eval("(car '[a b])").to_string(), "a");
+    assert_eq!(eval("(car (quote [(+ 1 2) 4]))").to_string(), "(+ 1 2)");
+    assert_eq!(eval("(car [])"), Value::Nil);
+}
+
+#[test]
+fn hash_map() {
+    let mut environment = Environment::new_configured();
+
+    environment
+        .eval_str("(set 'table (puthash \"two\" 2 (puthash 1 \"one\" (make-hash))))")
+        .unwrap();
+
+    let mut eval = |buffer| environment.eval_str(buffer);
+
+    assert_eq!(
+        eval("(gethash 1 table)").unwrap(),
+        Value::String("one".into())
+    );
+    assert_eq!(eval("(gethash \"two\" table)").unwrap(), Value::Integer(2));
+    assert_eq!(eval("(gethash 3 table)").unwrap(), Value::Nil);
+    assert_eq!(
+        eval("(gethash 3 table 'none)").unwrap().to_string(),
+        "'none"
+    );
+    assert_eq!(
+        eval("(gethash 'a (make-hash 'a 1 'b 2))").unwrap(),
+        Value::Integer(1)
+    );
+
+    // `puthash` leaves the original map alone.
+    eval("(puthash 1 'changed table)").unwrap();
+    assert_eq!(
+        eval("(gethash 1 table)").unwrap(),
+        Value::String("one".into())
+    );
+
+    assert_eq!(
+        eval("table").unwrap().to_string(),
+        "(make-hash \"two\" 2 1 \"one\")"
+    );
+    assert_eq!(
+        eval("(eval (read (to-string table)))").unwrap(),
+        eval("table").unwrap()
+    );
+
+    assert!(eval("(puthash [1] 1 (make-hash))").is_err());
+    assert!(eval("(gethash 1.5 table)").is_err());
+    assert!(eval("(gethash 1 [1 2])").is_err());
+    assert!(eval("(make-hash 1)").is_err());
+}
+
+#[test]
+fn gensym() {
+    let mut environment = Environment::new_configured();
+
+    let mut eval = |buffer| environment.eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(= (gensym) (gensym))"), Value::Nil);
+    assert_eq!(eval("(symbolp (gensym))"), Value::T);
+
+    // Names that are already taken are skipped.
+    eval("(set 'g4 1)");
+    eval("(defun g5 [] 1)");
+
+    assert_eq!(eval("(gensym)").to_string(), "g6");
+}
+
+#[test]
+fn symbol_name_intern() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer);
+
+    assert_eq!(
+        eval("(symbol-name 'foo)").unwrap(),
+        Value::String("foo".into())
+    );
+    assert_eq!(
+        eval("(intern \"bar\")").unwrap(),
+        Value::Symbol(Symbol::from_str("bar"))
+    );
+    assert_eq!(
+        eval("(make-symbol \"bar\")").unwrap(),
+        Value::Symbol(Symbol::new("bar".into(), Quote::None, false))
+    );
+    assert_eq!(eval("(symbolp (intern \"x\"))").unwrap(), Value::T);
+
+    // Round trips in both directions.
+    assert_eq!(
+        eval("(symbol-name (intern \"baz\"))").unwrap(),
+        Value::String("baz".into())
+    );
+    assert_eq!(
+        eval("(intern (symbol-name 'qux))").unwrap(),
+        Value::Symbol(Symbol::from_str("qux"))
+    );
+    assert_eq!(
+        eval("(= (intern \"a\") (intern (symbol-name (intern \"a\"))))").unwrap(),
+        Value::T
+    );
+
+    assert!(eval("(symbol-name \"foo\")").is_err());
+    assert!(eval("(intern 'foo)").is_err());
+    assert!(eval("(intern \"\")").is_err());
+}
+
+#[test]
+fn defmacro() {
+    let mut environment = Environment::new_configured();
+
+    environment
+        .eval_str(
+            "(defmacro swap [a b]
+               (let* [[tmp (gensym)]]
+                 `(let* [[,tmp ,a]]
+                    (set (quote ,a) ,b)
+                    (set (quote ,b) ,tmp))))",
+        )
+        .unwrap();
+
+    let mut eval = |buffer| environment.eval_str(buffer).unwrap();
+
+    eval("(set 'x 1)");
+    eval("(set 'y 2)");
+    eval("(swap x y)");
+
+    assert_eq!(eval("(list x y)").to_string(), "[2 1]");
+    assert_eq!(
+        eval("(macroexpand '(swap x y))").to_string(),
+        "(let* [[g2 x]] (set (quote x) y) (set (quote y) g2))"
+    );
+    assert_eq!(eval("(macroexpand '(+ 1 2))").to_string(), "(+ 1 2)");
+
+    // Macros get the argument forms themselves, not their values.
+    eval("(defmacro my-unless [condition body] `(if ,condition nil ,body))");
+
+    assert_eq!(eval("(my-unless nil (+ 1 2))"), Value::Integer(3));
+    assert_eq!(eval("(my-unless t (set 'z 1))"), Value::Nil);
+    assert!(environment.eval_str("z").is_err());
+}
+
+#[test]
+fn catch_throw() {
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(
+        environment
+            .eval_str("(catch 'done (progn (throw 'done 42) 0))")
+            .unwrap(),
+        Value::Integer(42)
+    );
+    assert_eq!(
+        environment.eval_str("(catch 'done 1 2)").unwrap(),
+        Value::Integer(2)
+    );
+
+    // Throws unwind through function calls and loops.
+    environment
+        .eval_str("(defun find-first [limit] (set 'i 0) (while t (when (> i limit) (throw 'found i)) (incf i)))")
+        .unwrap();
+
+    assert_eq!(
+        environment
+            .eval_str("(catch 'found (find-first 3))")
+            .unwrap(),
+        Value::Integer(4)
+    );
+
+    // An inner catch lets other tags through to the outer one.
+    assert_eq!(
+        environment
+            .eval_str("(catch 'outer (+ 1 (catch 'inner (throw 'outer 10))))")
+            .unwrap(),
+        Value::Integer(10)
+    );
+    assert_eq!(
+        environment
+            .eval_str("(catch 'outer (+ 1 (catch 'inner (throw 'inner 10))))")
+            .unwrap(),
+        Value::Integer(11)
+    );
+
+    assert!(matches!(
+        environment.eval_str("(throw 'nowhere 1)"),
+        Err(EvalError::Thrown { .. })
+    ));
+    assert!(matches!(
+        environment.eval_str("(catch 'other (throw 'nowhere 1))"),
+        Err(EvalError::Thrown { .. })
+    ));
+}
+
+#[test]
+fn condition_case() {
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(
+        environment
+            .eval_str("(condition-case e (error \"boom\") (debug e))")
+            .unwrap(),
+        Value::String("boom".into())
+    );
+    assert_eq!(
+        environment
+            .eval_str("(condition-case e (+ 1 2) (debug e))")
+            .unwrap(),
+        Value::Integer(3)
+    );
+
+    // Built-in errors are caught too.
+    assert_eq!(
+        environment
+            .eval_str("(condition-case e (/ 1 0) (concat \"caught: \" e))")
+            .unwrap(),
+        Value::String("caught: Division by zero: /".into())
+    );
+    assert_eq!(
+        environment
+            .eval_str("(condition-case nil (error \"boom\") \"handled\")")
+            .unwrap(),
+        Value::String("handled".into())
+    );
+
+    // The handler's binding doesn't leak out.
+    assert!(environment.eval_str("e").is_err());
+
+    assert!(matches!(
+        environment.eval_str("(error \"boom\")"),
+        Err(EvalError::UserError(message)) if message == "boom"
+    ));
+    assert!(matches!(
+        environment.eval_str("(condition-case e (error \"boom\") (error (concat e \"!\")))"),
+        Err(EvalError::UserError(message)) if message == "boom!"
+    ));
+    assert!(matches!(
+        environment.eval_str("(condition-case e (throw 'tag 1) 'handled)"),
+        Err(EvalError::Thrown { .. })
+    ));
+}
+
+#[test]
+fn while_loop() {
+    let mut environment = Environment::new_configured();
+
+    environment.eval_str("(set 'i 0)").unwrap();
+
+    assert_eq!(
+        environment
+            .eval_str("(while (< i 5) (incf i) (* i 10))")
+            .unwrap(),
+        Value::Integer(50)
+    );
+    assert_eq!(environment.eval_str("i").unwrap(), Value::Integer(5));
+
+    assert_eq!(
+        environment.eval_str("(while nil (incf i))").unwrap(),
+        Value::Nil
+    );
+    assert_eq!(environment.eval_str("i").unwrap(), Value::Integer(5));
+}
+
+#[test]
+fn unwind_protect() {
+    let mut environment = Environment::new_configured();
+
+    environment.eval_str("(set 'cleanups 0)").unwrap();
+
+    assert_eq!(
+        environment
+            .eval_str("(unwind-protect (+ 1 2) (incf cleanups))")
+            .unwrap(),
+        Value::Integer(3)
+    );
+    assert_eq!(environment.eval_str("cleanups").unwrap(), Value::Integer(1));
+
+    assert!(matches!(
+        environment.eval_str("(unwind-protect (error \"boom\") (incf cleanups) (incf cleanups))"),
+        Err(EvalError::UserError(message)) if message == "boom"
+    ));
+    assert_eq!(environment.eval_str("cleanups").unwrap(), Value::Integer(3));
+
+    // Cleanup also runs when unwinding to a catch.
+    assert_eq!(
+        environment
+            .eval_str("(catch 'done (unwind-protect (throw 'done 42) (incf cleanups)))")
+            .unwrap(),
+        Value::Integer(42)
+    );
+    assert_eq!(environment.eval_str("cleanups").unwrap(), Value::Integer(4));
+}
+
+#[test]
+fn min_max() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(max 3 7 2)"), Value::Integer(7));
+    assert_eq!(eval("(min 3 7 2)"), Value::Integer(2));
+    assert_eq!(eval("(max 5)"), Value::Integer(5));
+    assert_eq!(eval("(min -1 -4)"), Value::Integer(-4));
+    assert_eq!(eval("(max 1 2.5)"), Value::Float(2.5));
+    assert_eq!(eval("(min 1 2.5)"), Value::Integer(1));
+
+    let mut environment = Environment::new_configured();
+
+    assert!(environment.eval_str("(max)").is_err());
+    assert!(environment.eval_str("(min 1 \"two\")").is_err());
+}
+
+#[test]
+fn math() {
+    let eval = |buffer| Environment::new_configured().eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(abs -5)"), Value::Integer(5));
+    assert_eq!(eval("(abs 5)"), Value::Integer(5));
+    assert_eq!(eval("(abs -2.5)"), Value::Float(2.5));
+
+    assert_eq!(eval("(expt 2 10)"), Value::Integer(1024));
+    assert_eq!(eval("(expt -3 3)"), Value::Integer(-27));
+    assert_eq!(eval("(expt -3 2)"), Value::Integer(9));
+    assert_eq!(eval("(expt 5 0)"), Value::Integer(1));
+
+    assert_eq!(eval("(gcd 12 18)"), Value::Integer(6));
+    assert_eq!(eval("(gcd 12 18 8)"), Value::Integer(2));
+    assert_eq!(eval("(gcd -4 6)"), Value::Integer(2));
+    assert_eq!(eval("(gcd)"), Value::Integer(0));
+
+    assert_eq!(eval("(lcm 4 6)"), Value::Integer(12));
+    assert_eq!(eval("(lcm 2 3 4)"), Value::Integer(12));
+    assert_eq!(eval("(lcm -4 6)"), Value::Integer(12));
+    assert_eq!(eval("(lcm 0 5)"), Value::Integer(0));
+    assert_eq!(eval("(lcm)"), Value::Integer(1));
+
+    let mut environment = Environment::new_configured();
+
+    if cfg!(not(feature = "bignum")) {
+        assert!(matches!(
+            environment.eval_str("(expt 2 63)"),
+            Err(EvalError::ArithmeticOverflow(_))
+        ));
+    }
+    // The exponent used to be truncated to 32 bits, making this 2.
+    assert!(matches!(
+        environment.eval_str("(expt 2 4294967297)"),
+        Err(EvalError::ArithmeticOverflow(_))
+    ));
+    assert!(environment.eval_str("(expt 2 -1)").is_err());
+    assert!(environment.eval_str("(abs \"one\")").is_err());
+    assert!(environment.eval_str("(gcd 1.5 3)").is_err());
+}
+
+#[test]
+fn eval_quote() {
+    let mut environment = Environment::new_configured();
+
+    environment.eval_str("(set 'code '(+ 1 2))").unwrap();
+    environment.eval_str("(set 'name 'code)").unwrap();
+
+    assert_eq!(environment.eval_str("code").unwrap().to_string(), "(+ 1 2)");
+    assert_eq!(environment.eval_str(",code").unwrap(), Value::Integer(3));
+    assert_eq!(
+        environment.eval_str("(* 2 ,code)").unwrap(),
+        Value::Integer(6)
+    );
+
+    // The looked up value is evaluated once, not repeatedly.
+    assert_eq!(environment.eval_str(",name").unwrap().to_string(), "'code");
+
+    // Self-evaluating values stay as they are.
+    environment.eval_str("(set 'x 5)").unwrap();
+    assert_eq!(environment.eval_str(",x").unwrap(), Value::Integer(5));
+
+    assert!(matches!(
+        environment.eval_str(",nothing"),
+        Err(EvalError::VariableIsVoid(name)) if name == "nothing"
+    ));
+}
+
+#[test]
+fn symbol_quotes() {
+    let mut environment = Environment::new_configured();
+
+    environment.eval_str("(set 'x '(+ 1 2))").unwrap();
+
+    for (quote, expected) in [
+        (Quote::None, "(+ 1 2)"),
+        (Quote::Single, "'x"),
+        (Quote::Eval, "3"),
+    ] {
+        let symbol = Value::Symbol(Symbol::new("x".into(), quote, false));
+        assert_eq!(symbol.eval(&mut environment).unwrap().to_string(), expected);
+    }
+
+    for (quote, void) in [
+        (Quote::None, true),
+        (Quote::Single, false),
+        (Quote::Eval, true),
+    ] {
+        let symbol = Value::Symbol(Symbol::new("void".into(), quote, false));
+
+        match symbol.eval(&mut environment) {
+            Err(EvalError::VariableIsVoid(name)) => assert!(void && name == "void"),
+            Ok(_) => assert!(!void),
+            Err(error) => panic!("Unexpected error: {}", error),
+        }
+    }
+}
+
+#[test]
+fn defvar_defconst() {
+    let mut environment = Environment::new_configured();
+
+    let mut eval = |buffer| environment.eval_str(buffer).unwrap();
+
+    eval("(defvar counter 1 \"How many times it happened.\")");
+    assert_eq!(eval("counter"), Value::Integer(1));
+
+    // `defvar` doesn't clobber existing bindings, nor evaluate the value then.
+    eval("(set 'counter 5)");
+    eval("(defvar counter (error \"unreachable\"))");
+    assert_eq!(eval("counter"), Value::Integer(5));
+
+    assert_eq!(
+        eval("(variable-documentation 'counter)"),
+        Value::String("How many times it happened.".into())
+    );
+
+    eval("(defconst pi 3)");
+    assert_eq!(eval("pi"), Value::Integer(3));
+    assert_eq!(eval("(variable-documentation 'pi)"), Value::Nil);
+
+    // Local bindings may still shadow constants.
+    eval("(defun shadow [pi] (set 'pi (+ pi 1)) pi)");
+    assert_eq!(eval("(shadow 10)"), Value::Integer(11));
+
+    // Redefining a constant is fine, only assignment isn't.
+    eval("(defconst pi 4)");
+    assert_eq!(eval("pi"), Value::Integer(4));
+
+    assert!(matches!(
+        environment.eval_str("(set 'pi 5)"),
+        Err(EvalError::ConstantReassigned(name)) if name == "pi"
+    ));
+    assert!(environment.eval_str("(set pi 5)").is_err());
+    assert!(matches!(
+        environment.eval_str("(incf pi)"),
+        Err(EvalError::ConstantReassigned(_))
+    ));
+    assert_eq!(environment.eval_str("pi").unwrap(), Value::Integer(4));
+
+    assert!(environment.eval_str("(defvar)").is_err());
+    assert!(environment.eval_str("(defconst 1 2)").is_err());
+}
+
+#[test]
+fn makunbound() {
+    let mut environment = Environment::new_configured();
+
+    environment.eval_str("(set 'x 1)").unwrap();
+    environment.eval_str("(defun f [] 2)").unwrap();
+
+    assert_eq!(environment.eval_str("(boundp 'x)").unwrap(), Value::T);
+    assert_eq!(environment.eval_str("(fboundp 'f)").unwrap(), Value::T);
+
+    assert_eq!(
+        environment.eval_str("(makunbound 'x)").unwrap(),
+        Value::Symbol(Symbol::from_str("x"))
+    );
+    assert_eq!(environment.eval_str("(boundp 'x)").unwrap(), Value::Nil);
+    assert!(matches!(
+        environment.eval_str("x"),
+        Err(EvalError::VariableIsVoid(name)) if name == "x"
+    ));
+
+    assert_eq!(
+        environment.eval_str("(fmakunbound 'f)").unwrap(),
+        Value::Symbol(Symbol::from_str("f"))
+    );
+    assert_eq!(environment.eval_str("(fboundp 'f)").unwrap(), Value::Nil);
+    assert!(matches!(
+        environment.eval_str("(f)"),
+        Err(EvalError::FunctionDefinitionIsVoid(name, _)) if name == "f"
+    ));
+
+    // Only the innermost binding goes away.
+    environment.eval_str("(set 'y 1)").unwrap();
+    assert_eq!(
+        environment
+            .eval_str("(let* [[y 2]] (makunbound 'y) y)")
+            .unwrap(),
+        Value::Integer(1)
+    );
+
+    // Unbinding what isn't bound is fine.
+    assert!(environment.eval_str("(makunbound 'nothing)").is_ok());
+    assert!(environment.eval_str("(fmakunbound 'nothing)").is_ok());
+
+    environment.eval_str("(defconst c 1)").unwrap();
+    assert!(matches!(
+        environment.eval_str("(makunbound 'c)"),
+        Err(EvalError::ConstantReassigned(_))
+    ));
+    assert!(environment.eval_str("(makunbound 1)").is_err());
+}
+
+#[test]
+fn multiple_values() {
+    let mut environment = Environment::new_configured();
+
+    environment
+        .eval_str("(defun divide [x y] (values (/ x y) (% x y)))")
+        .unwrap();
+
+    assert_eq!(
+        environment
+            .eval_str("(multiple-value-bind [q r] (divide 7 2) [q r])")
+            .unwrap()
+            .to_string(),
+        "[3 1]"
+    );
+
+    // Single-value contexts only see the first value.
+    assert_eq!(
+        environment.eval_str("(divide 7 2)").unwrap(),
+        Value::Integer(3)
+    );
+    assert_eq!(
+        environment.eval_str("(+ 1 (divide 7 2))").unwrap(),
+        Value::Integer(4)
+    );
+    assert_eq!(
+        environment
+            .eval_str("(mapcar (lambda [x] (values x 0)) [1 2])")
+            .unwrap()
+            .to_string(),
+        "[1 2]"
+    );
+    assert_eq!(environment.eval_str("(values)").unwrap(), Value::Nil);
+
+    // Missing values are nil, extra ones are dropped, and plain values count as one.
+    assert_eq!(
+        environment
+            .eval_str("(multiple-value-bind [a b c] (values 1 2) [a b c])")
+            .unwrap()
+            .to_string(),
+        "[1 2 nil]"
+    );
+    assert_eq!(
+        environment
+            .eval_str("(multiple-value-bind [a] (progn (values 1 2)) a)")
+            .unwrap(),
+        Value::Integer(1)
+    );
+    assert_eq!(
+        environment
+            .eval_str("(multiple-value-bind [a b] 5 [a b])")
+            .unwrap()
+            .to_string(),
+        "[5 nil]"
+    );
+    assert_eq!(
+        Value::Values(vec![Value::Integer(1), Value::Integer(2)]).to_string(),
+        "(values 1 2)"
+    );
+
+    assert!(environment
+        .eval_str("(multiple-value-bind [1] (values 1) 1)")
+        .is_err());
+    assert!(environment.eval_str("(multiple-value-bind [a])").is_err());
+}
+
+#[test]
+fn let_dynamic() {
+    let mut environment = Environment::new_configured();
+
+    environment.eval_str("(defvar verbose nil)").unwrap();
+    environment
+        .eval_str("(defun verbosity [] verbose)")
+        .unwrap();
+
+    assert_eq!(
+        environment
+            .eval_str("(let-dynamic [[verbose t]] (verbosity))")
+            .unwrap(),
+        Value::T
+    );
+    assert_eq!(environment.eval_str("verbose").unwrap(), Value::Nil);
+
+    // Values are restored after an error too, and after being set in the body.
+    assert!(environment
+        .eval_str("(let-dynamic [[verbose 1]] (set 'verbose 2) (error \"oops\"))")
+        .is_err());
+    assert_eq!(environment.eval_str("verbose").unwrap(), Value::Nil);
+
+    // Variables that weren't bound before are unbound again.
+    assert_eq!(
+        environment
+            .eval_str("(let-dynamic [[fresh 1] [fresh 2]] fresh)")
+            .unwrap(),
+        Value::Integer(2)
+    );
+    assert_eq!(environment.eval_str("(boundp 'fresh)").unwrap(), Value::Nil);
+
+    environment.eval_str("(defconst limit 1)").unwrap();
+    assert!(matches!(
+        environment.eval_str("(let-dynamic [[limit 2]] limit)"),
+        Err(EvalError::ConstantReassigned(_))
+    ));
+    assert!(environment.eval_str("(let-dynamic 1)").is_err());
+    assert!(environment.eval_str("(let-dynamic [[1 2]])").is_err());
+}
+
+#[test]
+fn closures() {
+    let mut environment = Environment::new_configured();
+
+    let mut eval = |buffer| environment.eval_str(buffer).unwrap();
+
+    eval("(defun make-counter [] (let* [[n 0]] (lambda [] (incf n))))");
+    eval("(set 'counter (make-counter))");
+    eval("(set 'other (make-counter))");
+
+    assert_eq!(eval("(funcall counter)"), Value::Integer(1));
+    assert_eq!(eval("(funcall counter)"), Value::Integer(2));
+    assert_eq!(eval("(counter)"), Value::Integer(3));
+
+    // Each call makes a fresh binding.
+    assert_eq!(eval("(funcall other)"), Value::Integer(1));
+
+    // Arguments are captured too, and parameters shadow captured bindings.
+    eval("(defun make-adder [x] (lambda [y] (+ x y)))");
+    eval("(set 'add-two (make-adder 2))");
+
+    assert_eq!(eval("(funcall add-two 5)"), Value::Integer(7));
+    assert_eq!(
+        eval("(mapcar (make-adder 10) [1 2 3])").to_string(),
+        "[11 12 13]"
+    );
+    assert_eq!(
+        eval("(funcall (lambda [x] (funcall (make-adder 1) x)) 4)"),
+        Value::Integer(5)
+    );
+
+    // The captured variables stay out of the global scope.
+    assert!(environment.eval_str("n").is_err());
+    assert!(environment.eval_str("x").is_err());
+}
+
+#[test]
+fn optional_args() {
+    let mut environment = Environment::new_configured();
+
+    let mut eval = |buffer| environment.eval_str(buffer).unwrap();
+
+    eval("(defun greet [name &optional [greeting \"hi\"]] (concat greeting \" \" name))");
+
+    assert_eq!(eval("(greet \"x\")"), Value::String("hi x".into()));
+    assert_eq!(
+        eval("(greet \"x\" \"hello\")"),
+        Value::String("hello x".into())
+    );
+
+    // Optionals without a default are nil, and defaults may refer to earlier params.
+    eval("(defun range [from &optional to [step (if to 1 2)] rest...] (list from to step rest))");
+
+    assert_eq!(eval("(range 1)").to_string(), "[1 nil 2 []]");
+    assert_eq!(eval("(range 1 5)").to_string(), "[1 5 1 []]");
+    assert_eq!(eval("(range 1 5 3 4 5)").to_string(), "[1 5 3 [4 5]]");
+
+    assert_eq!(
+        eval("(funcall (lambda [&optional [x 10]] x))"),
+        Value::Integer(10)
+    );
+    assert_eq!(
+        eval("(lambda [a &optional b [c 1] d...] a)").to_string(),
+        "(lambda [a &optional b [c 1] d...] a)"
+    );
+
+    assert!(environment.eval_str("(greet)").is_err());
+    assert!(environment
+        .eval_str("(defun bad [&optional [x]] x)")
+        .is_err());
+    assert!(environment
+        .eval_str("(defun bad [&optional x &optional y] x)")
+        .is_err());
+    assert!(environment.eval_str("(defun bad [x... y] x)").is_err());
+}
+
+#[test]
+fn function_references() {
+    let mut environment = Environment::new_configured();
+
+    let mut eval = |buffer| environment.eval_str(buffer).unwrap();
+
+    eval("(defun square [x] (* x x))");
+
+    assert_eq!(
+        eval("(mapcar (function square) [1 2 3])").to_string(),
+        "[1 4 9]"
+    );
+    assert_eq!(eval("(mapcar #'square [4])").to_string(), "[16]");
+    assert_eq!(
+        eval("(mapcar (function car) [[1 2] [3 4]])").to_string(),
+        "[1 3]"
+    );
+    assert_eq!(eval("(reduce #'+ 0 [1 2 3])"), Value::Integer(6));
+
+    // References can be stored and called like any other function value.
+    eval("(set 'f #'square)");
+    assert_eq!(eval("(funcall f 5)"), Value::Integer(25));
+    assert_eq!(eval("(f 6)"), Value::Integer(36));
+
+    assert_eq!(parse("#'square").unwrap().to_string(), "(function square)");
+
+    assert!(matches!(
+        environment.eval_str("(function nothing)"),
+        Err(EvalError::FunctionDefinitionIsVoid(..))
+    ));
+    assert!(environment.eval_str("(function 1)").is_err());
+}
+
+#[test]
+fn booleans() {
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(Value::from(true), Value::T);
+    assert_eq!(Value::from(false), Value::Nil);
+
+    let mut eval = |buffer: &str| environment.eval_str(buffer).unwrap();
+
+    assert_eq!(eval("(booleanp t)"), Value::T);
+    assert_eq!(eval("(booleanp nil)"), Value::T);
+    assert_eq!(eval("(booleanp (= 1 1))"), Value::T);
+    assert_eq!(eval("(booleanp 1)"), Value::Nil);
+    assert_eq!(eval("(booleanp [])"), Value::Nil);
+
+    let predicates = [
+        "not", "integerp", "stringp", "symbolp", "listp", "null", "booleanp",
+    ];
+    let values = [
+        "t", "nil", "0", "1.5", "\"\"", "\"a\"", "'a", "[]", "[1]", "?c",
+    ];
+
+    for predicate in predicates {
+        for value in values {
+            let result = eval(&format!("({} {})", predicate, value));
+            assert!(
+                matches!(result, Value::T | Value::Nil),
+                "({} {}) returned {}",
+                predicate,
+                value,
+                result
+            );
+        }
+    }
+
+    for comparison in ["=", "/=", "<", ">", "<=", ">="] {
+        for (x, y) in [("1", "2"), ("2", "1"), ("1", "1"), ("1", "1.0")] {
+            let result = eval(&format!("({} {} {})", comparison, x, y));
+            assert!(matches!(result, Value::T | Value::Nil));
+        }
+    }
+}
+
+#[test]
+fn list_literals() {
+    let mut environment = Environment::new_configured();
+
+    let mut eval = |buffer| environment.eval_str(buffer).unwrap().to_string();
+
+    // Bracketed lists evaluate their elements, just like `list`.
+    assert_eq!(eval("[(+ 1 2) 3]"), "[3 3]");
+    assert_eq!(eval("(list (+ 1 2) 3)"), "[3 3]");
+    assert_eq!(eval("(car [(+ 1 2) 3])"), "3");
+    assert_eq!(eval("(cdr [3 (+ 1 2)])"), "[3]");
+
+    // Quoted ones are data.
+    assert_eq!(eval("'[(+ 1 2) 3]"), "[(+ 1 2) 3]");
+    assert_eq!(eval("(car '[(+ 1 2) 3])"), "(+ 1 2)");
+    assert_eq!(eval("(cdr '[3 (+ 1 2)])"), "[(+ 1 2)]");
+    assert_eq!(eval("(list '(+ 1 2))"), "[(+ 1 2)]");
+
+    // Elements are evaluated once, even when the list is stored and passed around.
+    eval("(set 'n 0)");
+    assert_eq!(eval("[(incf n) (incf n)]"), "[1 2]");
+    assert_eq!(eval("n"), "2");
+
+    eval("(set 'data '[(incf n)])");
+    assert_eq!(eval("data"), "[(incf n)]");
+    assert_eq!(eval("(car data)"), "(incf n)");
+    assert_eq!(eval("(length data)"), "1");
+    assert_eq!(eval("(mapcar (lambda [x] x) data)"), "[(incf n)]");
+    assert_eq!(eval("n"), "2");
+}
+
+#[test]
+fn setf() {
+    let mut environment = Environment::new_configured();
+
+    let mut eval = |buffer| environment.eval_str(buffer).unwrap().to_string();
+
+    eval("(set 'numbers [1 2 3])");
+    assert_eq!(eval("(setf (nth 1 numbers) 99)"), "99");
+    assert_eq!(eval("numbers"), "[1 99 3]");
+
+    eval("(setf (elt numbers 2) 7 (car numbers) 0)");
+    assert_eq!(eval("numbers"), "[0 99 7]");
+
+    eval("(set 'table (make-hash \"a\" 1))");
+    eval("(setf (gethash \"b\" table) 2)");
+    eval("(setf (gethash \"a\" table) 10)");
+    assert_eq!(eval("table"), "(make-hash \"a\" 10 \"b\" 2)");
+
+    // Places nest, updating the variable at the root.
+    eval("(set 'nested [[1 2] (make-hash 'k [0])])");
+    eval("(setf (nth 0 (nth 0 nested)) 5)");
+    eval("(setf (car (gethash 'k (nth 1 nested))) 6)");
+    assert_eq!(eval("nested"), "[[5 2] (make-hash 'k [6])]");
+
+    // Plain symbols work like `set`, respecting local bindings.
+    eval("(setf x 1)");
+    assert_eq!(eval("x"), "1");
+    eval("(defun bump [x] (setf x (+ x 1)) x)");
+    assert_eq!(eval("(bump 5)"), "6");
+    assert_eq!(eval("x"), "1");
+
+    assert!(environment.eval_str("(setf (nth 5 numbers) 1)").is_err());
+    assert!(environment.eval_str("(setf (+ 1 2) 1)").is_err());
+    assert!(environment.eval_str("(setf 1 2)").is_err());
+    assert!(environment.eval_str("(setf x)").is_err());
+}
+
+#[test]
+fn parse_all() {
+    use crate::parsers::parse_all;
+
+    let forms = parse_all("(defun f [] 1) ; comment\n 42 \"(\" [1 2]\n(f)").unwrap();
+
+    assert_eq!(
+        forms.iter().map(Value::to_string).collect::<Vec<String>>(),
+        ["(defun f [] 1)", "42", "\"(\"", "[1 2]", "(f)"]
+    );
+    assert!(parse_all("").unwrap().is_empty());
+    assert!(parse_all(" ; nothing but a comment\n").unwrap().is_empty());
+
+    let err = parse_all("(+ 1 2)\n  3)").unwrap_err();
+    assert_eq!(err.kind, ParserErrorKind::UnmatchedParentheses);
+    assert_eq!((err.line, err.column), (2, 4));
+
+    let err = parse_all("1\n(+ 1 \"\\q\")").unwrap_err();
+    assert_eq!((err.line, err.column), (2, 8));
+
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(
+        environment
+            .eval_file("test/multiple-forms.crisp".into())
+            .unwrap(),
+        Value::Integer(25)
+    );
+    assert_eq!(
+        environment.eval_str("(sum-of-squares 1 2)").unwrap(),
+        Value::Integer(5)
+    );
+}
+
+#[test]
+fn load() {
+    let mut environment = Environment::new_configured();
+
+    assert!(environment.eval_str("(square 3)").is_err());
+    assert_eq!(
+        environment
+            .eval_str("(load \"test/lib/square.crisp\")")
+            .unwrap(),
+        Value::Integer(1)
+    );
+    assert_eq!(
+        environment.eval_str("(square 3)").unwrap(),
+        Value::Integer(9)
+    );
+
+    // Loading again evaluates the file again.
+    environment
+        .eval_str("(load (concat \"test/lib/\" \"square.crisp\"))")
+        .unwrap();
+    assert_eq!(
+        environment.eval_str("square-loads").unwrap(),
+        Value::Integer(2)
+    );
+
+    assert!(matches!(
+        environment.eval_str("(load \"test/lib/missing.crisp\")"),
+        Err(EvalError::FailedToReadFile(_, _))
+    ));
+    assert!(environment.eval_str("(load 'square)").is_err());
+}
+
+#[test]
+fn require() {
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(
+        environment
+            .eval_str("(require \"test/lib/square.crisp\")")
+            .unwrap(),
+        Value::T
+    );
+    assert_eq!(
+        environment
+            .eval_str("(require \"test/lib/square.crisp\")")
+            .unwrap(),
+        Value::Nil
+    );
+    // Different spellings of the same path count as one file.
+    assert_eq!(
+        environment
+            .eval_str("(require \"./test/../test/lib/square.crisp\")")
+            .unwrap(),
+        Value::Nil
+    );
+
+    assert_eq!(
+        environment.eval_str("square-loads").unwrap(),
+        Value::Integer(1)
+    );
+    assert_eq!(
+        environment.eval_str("(square 4)").unwrap(),
+        Value::Integer(16)
+    );
+
+    assert!(matches!(
+        environment.eval_str("(require \"test/lib/missing.crisp\")"),
+        Err(EvalError::FailedToReadFile(_, _))
+    ));
+}
+
+#[test]
+fn packages() {
+    let mut environment = Environment::new_configured();
+
+    let mut eval = |buffer| environment.eval_str(buffer).unwrap().to_string();
+
+    eval("(in-package 'math)");
+    eval("(defun square [x] (* x x))");
+    eval("(defun sum-of-squares [x y] (+ (square x) (square y)))");
+    eval("(in-package 'geom)");
+    eval("(defun square [side] (list side side))");
+    eval("(in-package nil)");
+
+    assert_eq!(eval("(math:square 3)"), "9");
+    assert_eq!(eval("(geom:square 3)"), "[3 3]");
+
+    // Unqualified calls inside a package refer to its own functions.
+    assert_eq!(eval("(math:sum-of-squares 1 2)"), "5");
+
+    // The default package has neither, until it gets its own.
+    assert!(environment.eval_str("(square 3)").is_err());
+
+    let mut eval = |buffer| environment.eval_str(buffer).unwrap().to_string();
+
+    eval("(defun square [x] 'default)");
+    assert_eq!(eval("(square 3)"), "'default");
+    assert_eq!(eval("(math:square 3)"), "9");
+
+    // Builtins are visible from every package.
+    eval("(in-package 'math)");
+    assert_eq!(eval("(square 4)"), "16");
+    assert_eq!(eval("(+ 1 2)"), "3");
+    eval("(in-package nil)");
+
+    assert_eq!(eval("'math:square"), "'math:square");
+    assert_eq!(eval("(mapcar #'geom:square [1])"), "[[1 1]]");
+
+    assert_eq!(
+        parse("math:square").unwrap(),
+        Value::Symbol(Symbol::from_str("square").in_package(Some("math".into())))
+    );
+    assert_eq!(
+        parse(":key").unwrap(),
+        Value::Symbol(Symbol::from_str(":key"))
+    );
+
+    assert!(matches!(
+        environment.eval_str("(geom:area 1)"),
+        Err(EvalError::FunctionDefinitionIsVoid(name, _)) if name == "geom:area"
+    ));
+
+    // Qualified variables are distinct from unqualified ones.
+    environment.eval_str("(set 'math:x 1)").unwrap();
+    assert_eq!(environment.eval_str("math:x").unwrap(), Value::Integer(1));
+    assert!(environment.eval_str("x").is_err());
+    assert!(matches!(
+        environment.eval_str("geom:x"),
+        Err(EvalError::VariableIsVoid(name)) if name == "geom:x"
+    ));
+}
+
+#[test]
+fn trace() {
+    let mut environment = Environment::new_configured();
+    let output = Output::default();
+
+    environment.set_trace_output(Box::new(output.clone()));
+    environment
+        .eval_str("(defun fact [n] (if (= n 0) 1 (* n (fact (- n 1)))))")
+        .unwrap();
+
+    assert_eq!(
+        environment.eval_str("(trace 'fact)").unwrap().to_string(),
+        "fact"
+    );
+    assert_eq!(environment.eval_str("(fact 2)").unwrap(), Value::Integer(2));
+    assert_eq!(
+        output.contents(),
+        "(fact 2)\n    (fact 1)\n        (fact 0)\n        fact returned 1\n    fact returned 1\nfact returned 2\n"
+    );
+
+    output.clear();
+
+    assert_eq!(environment.eval_str("(untrace 'fact)").unwrap(), Value::T);
+    assert_eq!(environment.eval_str("(untrace 'fact)").unwrap(), Value::Nil);
+    assert_eq!(environment.eval_str("(fact 3)").unwrap(), Value::Integer(6));
+    assert!(output.contents().is_empty());
+
+    assert!(environment.eval_str("(trace '+)").is_err());
+    assert!(environment.eval_str("(trace 'undefined)").is_err());
+}
+
+#[test]
+fn did_you_mean() {
+    let mut environment = Environment::new_configured();
+
+    let suggestion = |environment: &mut Environment, buffer| match environment.eval_str(buffer) {
+        Err(EvalError::FunctionDefinitionIsVoid(_, suggestion)) => suggestion,
+        result => panic!("Expected a void function, got {:?}", result),
+    };
+
+    assert_eq!(
+        suggestion(&mut environment, "(cr [1 2])"),
+        Some("car".into())
+    );
+    assert_eq!(
+        suggestion(&mut environment, "(lenght [1 2])"),
+        Some("length".into())
+    );
+    assert_eq!(suggestion(&mut environment, "(frobnicate 1)"), None);
+
+    environment.eval_str("(defun fibonacci [n] n)").unwrap();
+    assert_eq!(
+        suggestion(&mut environment, "(fibonaci 1)"),
+        Some("fibonacci".into())
+    );
+    assert_eq!(
+        suggestion(&mut environment, "(trace 'fibonaci)"),
+        Some("fibonacci".into())
+    );
+
+    let mut repl = Repl::new(environment);
+    assert!(repl
+        .eval("(cr [1 2])")
+        .starts_with("Function definition is void: cr. Did you mean car?\n"));
+}
+
+#[test]
+fn backtrace() {
+    let mut environment = Environment::new_configured();
+
+    environment
+        .eval_str("(defun inner [] (+ 1 missing))")
+        .unwrap();
+    environment.eval_str("(defun middle [] (inner))").unwrap();
+    environment.eval_str("(defun outer [] (middle))").unwrap();
+
+    assert!(environment.eval_str("(outer)").is_err());
+    assert_eq!(
+        environment.take_backtrace(),
+        vec!["+", "inner", "middle", "outer"]
+    );
+    assert!(environment.take_backtrace().is_empty());
+
+    // Handled errors don't leave a backtrace behind.
+    environment
+        .eval_str("(condition-case nil (outer) \"handled\")")
+        .unwrap();
+    environment
+        .eval_str("(catch 'done (throw 'done 1))")
+        .unwrap();
+
+    assert!(environment.take_backtrace().is_empty());
+
+    // Each evaluation starts afresh, even if the last backtrace wasn't taken.
+    assert!(environment.eval_str("(outer)").is_err());
+    assert!(environment.eval_str("(middle)").is_err());
+    assert_eq!(environment.take_backtrace(), vec!["+", "inner", "middle"]);
+
+    assert!(environment.eval_str("(outer)").is_err());
+    environment.eval_str("1").unwrap();
+    assert!(environment.take_backtrace().is_empty());
+
+    let mut output: Vec<u8> = Vec::new();
+    let error = crate::run_tasks(
+        &mut environment,
+        vec![crate::Task::Eval("(middle)".into())],
+        &mut output,
+    )
+    .unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "Variable is void: missing\nBacktrace:\n  in +\n  in inner\n  in middle"
+    );
+
+    let mut repl = Repl::new(environment);
+
+    assert_eq!(
+        repl.eval("(middle)"),
+        "Variable is void: missing\nBacktrace:\n  in +\n  in inner\n  in middle"
+    );
+    assert_eq!(repl.eval("missing"), "Variable is void: missing");
+}
+
+#[cfg(feature = "bignum")]
+#[test]
+fn bignum() {
+    let mut environment = Environment::new_configured();
+    let mut eval = |buffer| environment.eval_str(buffer).unwrap().to_string();
+
+    eval("(defun factorial [n] (if (<= n 1) 1 (* n (factorial (- n 1)))))");
+
+    assert_eq!(eval("(factorial 12)"), "479001600");
+    assert_eq!(eval("(factorial 25)"), "15511210043330985984000000");
+    assert_eq!(eval("(/ (factorial 25) (factorial 24))"), "25");
+    assert_eq!(eval("(< (factorial 12) (factorial 13))"), "t");
+
+    assert_eq!(
+        eval("1234567890123456789012345678901234567890"),
+        "1234567890123456789012345678901234567890"
+    );
+    assert_eq!(eval("-0x10000000000000000"), "-18446744073709551616");
+    assert_eq!(eval("(+ 9223372036854775807 1)"), "9223372036854775808");
+    assert_eq!(eval("(* 1.5 10000000000)"), "15000000000.0");
+
+    assert_eq!(
+        environment.eval_str("(- 9223372036854775808 1)").unwrap(),
+        Value::Integer(Integer::MAX)
+    );
+
+    let mut eval = |buffer| environment.eval_str(buffer).unwrap().to_string();
+
+    assert_eq!(eval("(integerp 100000000000000000000)"), "t");
+    assert_eq!(eval("(- -9223372036854775808)"), "9223372036854775808");
+    assert_eq!(eval("(abs -9223372036854775808)"), "9223372036854775808");
+    assert_eq!(
+        eval("(abs -100000000000000000000)"),
+        "100000000000000000000"
+    );
+    assert_eq!(eval("(mod 100000000000000000000 7)"), "2");
+    assert_eq!(eval("(mod -100000000000000000000 7)"), "5");
+    assert_eq!(eval("(expt 10 30)"), "1000000000000000000000000000000");
+    assert_eq!(
+        eval("(expt 100000000000000000000 2)"),
+        "10000000000000000000000000000000000000000"
+    );
+    assert_eq!(eval("(gcd 100000000000000000000 15)"), "5");
+    assert_eq!(eval("(gcd -9223372036854775808)"), "9223372036854775808");
+    assert_eq!(
+        eval("(lcm 100000000000000000000 3)"),
+        "300000000000000000000"
+    );
+    assert_eq!(
+        eval("(multiple-value-bind [q r] (floor 100000000000000000000 3) [q r])"),
+        "[33333333333333333333 1]"
+    );
+    assert_eq!(
+        eval("(multiple-value-bind [q r] (ceiling 100000000000000000000 -3) [q r])"),
+        "[-33333333333333333333 1]"
+    );
+    assert_eq!(
+        eval("(multiple-value-bind [q r] (round -9223372036854775808 -1) [q r])"),
+        "[9223372036854775808 0]"
+    );
+    assert_eq!(eval("(truncate 1e20)"), "100000000000000000000");
+}
+
+#[test]
+fn ratios() {
+    assert_eq!(parse("1/3").unwrap(), Value::Ratio(1, 3));
+    assert_eq!(parse("2/4").unwrap(), Value::Ratio(1, 2));
+    assert_eq!(parse("-6/4").unwrap(), Value::Ratio(-3, 2));
+    assert_eq!(parse("+3/1").unwrap(), Value::Integer(3));
+    assert_eq!(parse("0/5").unwrap(), Value::Integer(0));
+    assert!(parse("1/0").is_err());
+    assert!(parse("1/99999999999999999999").is_err());
+
+    let mut environment = Environment::new_configured();
+    let mut eval = |buffer| environment.eval_str(buffer).unwrap().to_string();
+
+    assert_eq!(eval("2/4"), "1/2");
+    assert_eq!(eval("(+ 1/2 1/3)"), "5/6");
+    assert_eq!(eval("(+ 1/2 1/2)"), "1");
+    assert_eq!(eval("(- 1/2 1)"), "-1/2");
+    assert_eq!(eval("(* 2/3 3/4)"), "1/2");
+    assert_eq!(eval("(/ 1/2 2)"), "1/4");
+    assert_eq!(eval("(/ 3 1/3)"), "9");
+    assert_eq!(eval("(+ 1/2 0.25)"), "0.75");
+    assert_eq!(eval("(- 1/3)"), "-1/3");
+    assert_eq!(eval("(abs -1/3)"), "1/3");
+
+    assert_eq!(eval("(< 1/3 1/2 1)"), "t");
+    assert_eq!(eval("(= 2/4 1/2)"), "t");
+    assert_eq!(eval("(max 1/3 1/4)"), "1/3");
+    assert_eq!(eval("(sort [1/2 1/3 1])"), "[1/3 1/2 1]");
+
+    // Plain integer division still truncates.
+    assert_eq!(eval("(/ 1 3)"), "0");
+
+    assert!(environment.eval_str("(/ 1/2 0)").is_err());
+    assert!(environment
+        .eval_str("(* 1/9223372036854775807 1/9223372036854775807)")
+        .is_err());
+}
+
+#[test]
+fn file_io() {
+    let path = std::env::temp_dir().join(format!("crisp-file-io-{}.txt", std::process::id()));
+    let name = Value::String(path.to_string_lossy().into_owned());
+
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(
+        environment
+            .eval_str(&format!("(write-file {} \"one\\ntwo\")", name))
+            .unwrap(),
+        Value::T
+    );
+    assert_eq!(
+        environment
+            .eval_str(&format!("(read-file {})", name))
+            .unwrap(),
+        Value::String("one\ntwo".into())
+    );
+
+    // Writing replaces what was there.
+    environment
+        .eval_str(&format!("(write-file {} (concat \"three\" \"\"))", name))
+        .unwrap();
+    assert_eq!(
+        environment
+            .eval_str(&format!("(read-file {})", name))
+            .unwrap(),
+        Value::String("three".into())
+    );
+
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(matches!(
+        environment.eval_str(&format!("(read-file {})", name)),
+        Err(EvalError::FailedToReadFile(_, _))
+    ));
+    assert!(matches!(
+        environment.eval_str("(write-file \"test/missing/file.txt\" \"\")"),
+        Err(EvalError::FailedToWriteFile(_, _))
+    ));
+    assert!(environment.eval_str("(write-file \"x.txt\" 1)").is_err());
+    assert!(environment.eval_str("(read-file)").is_err());
+}
+
+#[test]
+fn getenv() {
+    let mut environment = Environment::new_configured();
+
+    std::env::set_var("CRISP_TEST_GETENV", "value");
+
+    assert_eq!(
+        environment
+            .eval_str("(getenv \"CRISP_TEST_GETENV\")")
+            .unwrap(),
+        Value::String("value".into())
+    );
+    assert_eq!(
+        environment
+            .eval_str("(getenv \"CRISP_TEST_GETENV_UNSET\")")
+            .unwrap(),
+        Value::Nil
+    );
+
+    assert!(environment.eval_str("(getenv 'HOME)").is_err());
+    assert!(environment.eval_str("(getenv)").is_err());
+}
+
+#[test]
+fn command_line_args() {
+    let split =
+        |args: &[&str]| crate::split_script_args(args.iter().map(|arg| arg.to_string()).collect());
+
+    assert_eq!(
+        split(&["script.crisp", "--", "foo", "--bar", "--"]),
+        (
+            vec!["script.crisp".to_string()],
+            vec!["foo".to_string(), "--bar".to_string(), "--".to_string()]
+        )
+    );
+    assert_eq!(
+        split(&["-e", "x", "--"]),
+        (vec!["-e".to_string(), "x".to_string()], Vec::new())
+    );
+    assert_eq!(
+        split(&["a.crisp"]),
+        (vec!["a.crisp".to_string()], Vec::new())
+    );
+
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(
+        environment.eval_str("(command-line-args)").unwrap(),
+        Value::List(Vec::new())
+    );
+
+    environment.set_command_line_args(vec!["foo".into(), "bar".into()]);
+
+    assert_eq!(
+        environment.eval_str("(command-line-args)").unwrap(),
+        Value::List(vec![
+            Value::String("foo".into()),
+            Value::String("bar".into())
+        ])
+    );
+    assert!(environment.eval_str("(command-line-args 1)").is_err());
+}
+
+#[test]
+fn now() {
+    let mut environment = Environment::new_configured();
+
+    let first = environment.eval_str("(now)").unwrap();
+    let second = environment.eval_str("(now)").unwrap();
+
+    match (first, second) {
+        (Value::Float(first), Value::Float(second)) => {
+            assert!(first > 1.0e9);
+            assert!(second >= first);
+        }
+        result => panic!("Expected two floats, got {:?}", result),
+    }
+
+    assert_eq!(
+        environment
+            .eval_str("(progn (let 'start (now)) (>= (- (now) start) 0))")
+            .unwrap(),
+        Value::T
+    );
+    assert!(environment.eval_str("(now 1)").is_err());
+}
+
+#[test]
+fn eval_all() {
+    let mut environment = Environment::new_configured();
+
+    assert_eq!(
+        environment.eval_all("(set 'x 1) (+ x 1) \"s\"").unwrap(),
+        vec![
+            Value::Integer(1),
+            Value::Integer(2),
+            Value::String("s".into())
+        ]
+    );
+    assert_eq!(environment.eval_all("").unwrap(), Vec::new());
+    assert_eq!(environment.eval_all("; nothing").unwrap(), Vec::new());
+
+    assert!(environment
+        .eval_all("(set 'y 1) missing (set 'y 2)")
+        .is_err());
+    assert_eq!(environment.eval_str("y").unwrap(), Value::Integer(1));
+    assert!(environment.eval_all("1 (2").is_err());
+}